@@ -1,4 +1,6 @@
 // frontend/src/api/index.ts
+import { ProbeResult } from "../types/api.types";
+
 /**
  * Fetches the current application version
  */
@@ -60,6 +62,34 @@ export async function postCookie(cookie: string) {
   return response;
 }
 
+/**
+ * Probes a cookie against Claude without adding it.
+ * @param cookie The cookie string to probe
+ * @returns The diagnosis of the cookie
+ *
+ * Possible Status Codes:
+ * - 200: Success with the probe result
+ * - 400: Invalid cookie format
+ * - 401: Invalid bearer token
+ */
+export async function testCookie(cookie: string): Promise<ProbeResult> {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/cookie/test", {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Authorization: `Bearer ${token}`,
+    },
+    body: JSON.stringify({ cookie }),
+  });
+
+  if (!response.ok) {
+    throw new Error(`Error ${response.status}: ${response.statusText}`);
+  }
+
+  return await response.json();
+}
+
 /**
  * Gets cookie status information from the server.
  * @returns The cookie status data
@@ -110,6 +140,98 @@ export async function deleteCookie(cookie: string) {
   return response;
 }
 
+/**
+ * Bootstraps all invalid cookies again and restores the ones that pass
+ * @returns Response from the server with the result of each cookie
+ * - 200: Success
+ * - 401: Invalid bearer token
+ * - 409: A revalidation is already running
+ */
+export async function revalidateCookies() {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/cookies/revalidate", {
+    method: "POST",
+    headers: {
+      Authorization: `Bearer ${token}`,
+    },
+  });
+
+  return response;
+}
+
+/**
+ * Sets the groups of a cookie
+ * @param cookie The cookie to update
+ * @param groups The new groups of the cookie
+ */
+export async function setCookieGroups(cookie: string, groups: string[]) {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/cookie/groups", {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Authorization: `Bearer ${token}`,
+    },
+    body: JSON.stringify({ cookie, groups }),
+  });
+
+  return response;
+}
+
+/**
+ * Sets the dispatch weight of a cookie
+ * @param cookie The cookie to update
+ * @param weight The new weight of the cookie, 0 to never pick it randomly
+ */
+export async function setCookieWeight(cookie: string, weight: number) {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/cookie/weight", {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Authorization: `Bearer ${token}`,
+    },
+    body: JSON.stringify({ cookie, weight }),
+  });
+
+  return response;
+}
+
+/**
+ * Exports all cookies
+ * @param format "text" for one cookie per line, "json" for the full status
+ */
+export async function exportCookies(format: "text" | "json" = "text") {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch(`/api/cookies/export?format=${format}`, {
+    method: "GET",
+    headers: {
+      Authorization: `Bearer ${token}`,
+    },
+  });
+
+  return response;
+}
+
+/**
+ * Triggers a health check of all cookies
+ * @returns Response from the server
+ * - 202: Check started
+ * - 401: Invalid bearer token
+ * - 409: A check is already running
+ */
+export async function checkCookies() {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/cookies/check", {
+    method: "POST",
+    headers: {
+      Authorization: `Bearer ${token}`,
+    },
+  });
+
+  return response;
+}
+
 /**
  * Fetches the config data from the server
  */
@@ -172,58 +294,49 @@ export async function saveConfig(configData: any) {
  */
 export async function postMultipleCookies(cookies: string[]) {
   const token = localStorage.getItem("authToken") || "";
-  const results = [];
+  const messages: Record<string, string> = {
+    accepted: "Cookie submitted successfully",
+    duplicate: "Cookie already exists",
+    malformed: "Invalid cookie format",
+    wasted: "Cookie is already marked as invalid",
+    rejected: "Cookie failed the probe",
+  };
 
-  for (const cookie of cookies) {
-    try {
-      const response = await fetch("/api/cookie", {
-        method: "POST",
-        headers: {
-          "Content-Type": "application/json",
-          Authorization: `Bearer ${token}`,
-        },
-        body: JSON.stringify({ cookie }),
-      });
-
-      if (response.status === 400) {
-        results.push({
-          cookie,
-          success: false,
-          message: "Invalid cookie format",
-        });
-      } else if (response.status === 401) {
-        results.push({
-          cookie,
-          success: false,
-          message: "Authentication failed. Please set a valid auth token.",
-        });
-      } else if (response.status === 500) {
-        results.push({
-          cookie,
-          success: false,
-          message: "Server error.",
-        });
-      } else if (!response.ok) {
-        results.push({
-          cookie,
-          success: false,
-          message: `Error ${response.status}: ${response.statusText}`,
-        });
-      } else {
-        results.push({
-          cookie,
-          success: true,
-          message: "Cookie submitted successfully",
-        });
-      }
-    } catch (error) {
-      results.push({
-        cookie,
-        success: false,
-        message: error instanceof Error ? error.message : "Unknown error",
-      });
+  try {
+    const response = await fetch("/api/cookies/bulk", {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+        Authorization: `Bearer ${token}`,
+      },
+      body: JSON.stringify(cookies),
+    });
+
+    if (!response.ok) {
+      const message =
+        response.status === 401
+          ? "Authentication failed. Please set a valid auth token."
+          : `Error ${response.status}: ${response.statusText}`;
+      return cookies.map((cookie) => ({ cookie, success: false, message }));
     }
-  }
 
-  return results;
+    const data: {
+      results: {
+        cookie: string;
+        status: string;
+        probe?: { status: string; message?: string };
+      }[];
+    } = await response.json();
+    return data.results.map(({ cookie, status, probe }) => ({
+      cookie,
+      success: status === "accepted",
+      message:
+        status === "rejected" && probe
+          ? `${messages.rejected}: ${probe.message ?? probe.status}`
+          : messages[status] ?? status,
+    }));
+  } catch (error) {
+    const message = error instanceof Error ? error.message : "Unknown error";
+    return cookies.map((cookie) => ({ cookie, success: false, message }));
+  }
 }
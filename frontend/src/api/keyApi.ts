@@ -1,4 +1,5 @@
 // frontend/src/api/keyApi.ts
+import { ProbeResult } from "../types/api.types";
 import { KeyStatusInfo } from "../types/key.types";
 
 /**
@@ -8,7 +9,7 @@ import { KeyStatusInfo } from "../types/key.types";
  *
  * Possible Status Codes:
  * - 200: Success
- * - 400: Invalid key format
+ * - 400: Invalid key format, or the key failed the probe on submit
  * - 401: Invalid bearer token
  * - 500: Server error
  */
@@ -38,6 +39,34 @@ export async function postKey(key: string) {
   return response;
 }
 
+/**
+ * Probes a key against Gemini without adding it.
+ * @param key The key string to probe
+ * @returns The diagnosis of the key
+ *
+ * Possible Status Codes:
+ * - 200: Success with the probe result
+ * - 400: Invalid key format
+ * - 401: Invalid bearer token
+ */
+export async function testKey(key: string): Promise<ProbeResult> {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/key/test", {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Authorization: `Bearer ${token}`,
+    },
+    body: JSON.stringify({ key }),
+  });
+
+  if (!response.ok) {
+    throw new Error(`Error ${response.status}: ${response.statusText}`);
+  }
+
+  return await response.json();
+}
+
 /**
  * Gets key status information from the server.
  * @returns The key status data
@@ -0,0 +1,86 @@
+// frontend/src/api/vertexApi.ts
+import { VertexStatusInfo } from "../types/vertex.types";
+
+/**
+ * Adds a Vertex service account to the pool, or updates its overrides.
+ * @param credential The service account key JSON string
+ * @param modelId Optional model used with this credential
+ * @param location Optional location of the Vertex endpoint
+ * @returns The fetch response object
+ *
+ * Possible Status Codes:
+ * - 200: Success
+ * - 400: Invalid credential
+ * - 401: Invalid bearer token
+ * - 500: Server error
+ */
+export async function postVertexCredential(
+  credential: string,
+  modelId?: string,
+  location?: string
+) {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/vertex", {
+    method: "POST",
+    headers: {
+      "Content-Type": "application/json",
+      Authorization: `Bearer ${token}`,
+    },
+    body: JSON.stringify({
+      credential: JSON.parse(credential),
+      model_id: modelId || null,
+      location: location || null,
+    }),
+  });
+
+  if (!response.ok) {
+    throw new Error(`Error ${response.status}: ${response.statusText}`);
+  }
+
+  return response;
+}
+
+/**
+ * Gets the Vertex credentials from the server, without private keys.
+ * @returns The Vertex status data
+ */
+export async function getVertexStatus(): Promise<VertexStatusInfo> {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/vertex", {
+    method: "GET",
+    headers: {
+      "Content-Type": "application/json",
+      Authorization: `Bearer ${token}`,
+    },
+  });
+
+  if (!response.ok) {
+    throw new Error(`Error ${response.status}: ${response.statusText}`);
+  }
+
+  return await response.json();
+}
+
+/**
+ * Removes a Vertex credential from the pool.
+ * @param clientEmail Client email of the service account
+ * @returns The fetch response object
+ *
+ * Possible Status Codes:
+ * - 204: Success (No Content)
+ * - 401: Invalid bearer token
+ * - 500: Credential not found or server error
+ */
+export async function deleteVertexCredential(clientEmail: string) {
+  const token = localStorage.getItem("authToken") || "";
+  const response = await fetch("/api/vertex", {
+    method: "DELETE",
+    headers: {
+      "Content-Type": "application/json",
+      Authorization: `Bearer ${token}`,
+    },
+    body: JSON.stringify({ client_email: clientEmail }),
+  });
+
+  return response;
+}
@@ -152,6 +152,20 @@ const ConfigForm: React.FC<ConfigFormProps> = ({ config, onChange }) => {
             label={t("config.sections.api.preserveChats")}
           />
 
+          <ConfigCheckbox
+            name="reuse_conversations"
+            checked={config.reuse_conversations ?? false}
+            onChange={onChange}
+            label={t("config.sections.api.reuseConversations")}
+          />
+
+          <ConfigCheckbox
+            name="buffer_first_token"
+            checked={config.buffer_first_token ?? true}
+            onChange={onChange}
+            label={t("config.sections.api.bufferFirstToken")}
+          />
+
           <ConfigCheckbox
             name="web_search"
             checked={config.web_search}
@@ -232,6 +246,12 @@ const ConfigForm: React.FC<ConfigFormProps> = ({ config, onChange }) => {
           onChange={onChange}
           label={t("config.sections.cookie.skipRateLimit")}
         />
+        <ConfigCheckbox
+          name="probe_on_submit"
+          checked={config.probe_on_submit ?? false}
+          onChange={onChange}
+          label={t("config.sections.cookie.probeOnSubmit")}
+        />
       </ConfigSection>
 
       {/* Prompt Configurations Section */}
@@ -12,6 +12,8 @@ import DeleteButton from "./DeleteButton";
 // Default empty state
 const emptyKeyStatus: KeyStatusInfo = {
   valid: [],
+  exhausted: [],
+  invalid: [],
 };
 
 const KeyVisualization: React.FC = () => {
@@ -31,6 +33,8 @@ const KeyVisualization: React.FC = () => {
       const data = await getKeyStatus();
       const safeData: KeyStatusInfo = {
         valid: Array.isArray(data?.valid) ? data.valid : [],
+        exhausted: Array.isArray(data?.exhausted) ? data.exhausted : [],
+        invalid: Array.isArray(data?.invalid) ? data.invalid : [],
       };
       setKeyStatus(safeData);
     } catch (err) {
@@ -79,7 +83,10 @@ const KeyVisualization: React.FC = () => {
   };
 
   // Calculate total key count
-  const totalKeys = keyStatus.valid.length;
+  const totalKeys =
+    keyStatus.valid.length +
+    keyStatus.exhausted.length +
+    keyStatus.invalid.length;
 
   return (
     <div className="space-y-6 w-full">
@@ -176,6 +183,17 @@ const KeyVisualization: React.FC = () => {
                     <KeyValue keyString={status.key} />
                   </div>
                   <div className="flex items-center space-x-3">
+                    {status.stats && (
+                      <span className="text-cyan-400 bg-cyan-900/30 px-2 py-0.5 rounded text-xs">
+                        {t("keyStatus.today", {
+                          requests: status.stats.requests,
+                          tokens:
+                            status.stats.prompt_tokens +
+                            status.stats.candidate_tokens +
+                            status.stats.thought_tokens,
+                        })}
+                      </span>
+                    )}
                     {typeof status.count_403 === "number" && (
                       <span className="text-orange-400 bg-orange-900/30 px-2 py-0.5 rounded text-xs">
                         403: {status.count_403}
@@ -193,6 +211,72 @@ const KeyVisualization: React.FC = () => {
         )}
       </div>
 
+      {/* Exhausted Keys Section */}
+      {keyStatus.exhausted.length > 0 && (
+        <div className="rounded-lg border border-amber-800 bg-amber-900/20 p-4">
+          <h4 className="text-amber-300 font-medium mb-3">
+            {t("keyStatus.sections.exhausted")}
+          </h4>
+          <div className="space-y-2">
+            {keyStatus.exhausted.map((status, index) => (
+              <div
+                key={index}
+                className="py-2 text-sm text-gray-300 flex flex-wrap justify-between items-start border-b border-amber-800/30 last:border-0"
+              >
+                <div className="text-amber-300 flex-grow mr-4 min-w-0 mb-1 sm:mb-0">
+                  <KeyValue keyString={status.key} />
+                </div>
+                <div className="flex items-center space-x-3">
+                  {status.reset_time && (
+                    <span className="text-amber-400 bg-amber-900/30 px-2 py-0.5 rounded text-xs">
+                      {t("keyStatus.resetAt", {
+                        time: new Date(status.reset_time * 1000).toLocaleString(),
+                      })}
+                    </span>
+                  )}
+                  <DeleteButton
+                    keyString={status.key}
+                    onDelete={handleDeleteKey}
+                    isDeleting={deletingKey === status.key}
+                  />
+                </div>
+              </div>
+            ))}
+          </div>
+        </div>
+      )}
+
+      {/* Invalid Keys Section */}
+      {keyStatus.invalid.length > 0 && (
+        <div className="rounded-lg border border-red-800 bg-red-900/20 p-4">
+          <h4 className="text-red-300 font-medium mb-3">
+            {t("keyStatus.sections.invalid")}
+          </h4>
+          <div className="space-y-2">
+            {keyStatus.invalid.map((status, index) => (
+              <div
+                key={index}
+                className="py-2 text-sm text-gray-300 flex flex-wrap justify-between items-start border-b border-red-800/30 last:border-0"
+              >
+                <div className="text-red-300 flex-grow mr-4 min-w-0 mb-1 sm:mb-0">
+                  <KeyValue keyString={status.key} />
+                </div>
+                <div className="flex items-center space-x-3">
+                  <span className="text-red-400 bg-red-900/30 px-2 py-0.5 rounded text-xs">
+                    {status.reason}
+                  </span>
+                  <DeleteButton
+                    keyString={status.key}
+                    onDelete={handleDeleteKey}
+                    isDeleting={deletingKey === status.key}
+                  />
+                </div>
+              </div>
+            ))}
+          </div>
+        </div>
+      )}
+
       {/* No Keys Help Text */}
       {!loading && totalKeys === 0 && (
         <div className="mt-4 px-4 py-3 bg-gray-800/50 border border-gray-700 rounded-md">
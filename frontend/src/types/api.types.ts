@@ -11,3 +11,14 @@ export interface CookieSubmitRequest {
 export interface VersionResponse {
   version: string;
 }
+
+export interface ProbeResult {
+  ok: boolean;
+  status: "valid" | "rate_limited" | "restricted" | "invalid" | "error";
+  code: number | null;
+  message: string | null;
+  reason?: unknown;
+  reset_time: number | null;
+  models?: number;
+  capabilities?: string[];
+}
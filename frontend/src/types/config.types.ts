@@ -5,6 +5,7 @@ export interface ConfigData {
 
   // Vertex settings
   vertex: VertexConfig;
+  webhook?: WebhookConfig;
 
   // App settings
   check_update: boolean;
@@ -13,12 +14,15 @@ export interface ConfigData {
   // Network settings
   password: string;
   admin_password: string;
+  api_keys?: Record<string, string[]>;
   proxy: string | null;
   rproxy: string | null;
 
   // API settings
   max_retries: number;
   preserve_chats: boolean;
+  reuse_conversations?: boolean;
+  buffer_first_token?: boolean;
   web_search: boolean;
 
   // Cache settings
@@ -33,6 +37,22 @@ export interface ConfigData {
   skip_non_pro: boolean;
   skip_rate_limit: boolean;
   skip_normal_pro: boolean;
+  max_concurrency_per_cookie?: number;
+  cookie_wait_timeout?: number;
+  affinity_ttl?: number;
+  affinity_messages?: number;
+  health_check_interval?: number;
+  health_check_concurrency?: number;
+  revalidate_interval?: number;
+  key_invalid_threshold?: number;
+  key_cooldown?: number;
+  probe_on_submit?: boolean;
+  dispatch_strategy?:
+    | "round_robin"
+    | "least_recently_used"
+    | "least_in_flight"
+    | "random_weighted"
+    | "prefer_capabilities";
 
   // Prompt configurations
   use_real_roles: boolean;
@@ -46,6 +66,23 @@ export interface ConfigData {
 interface VertexConfig {
   credential: string | null;
   model_id: string | null;
+  location?: string | null;
+  model_locations?: Record<string, string>;
+  base_url?: string | null;
+}
+
+export interface WebhookTarget {
+  url: string;
+  format?: "json" | "discord" | "telegram";
+  chat_id?: string | null;
+}
+
+export interface WebhookConfig {
+  targets: WebhookTarget[];
+  pool_threshold: number;
+  key_403_threshold: number;
+  min_interval: number;
+  max_retries: number;
 }
 
 export interface ConfigState {
@@ -1,12 +1,42 @@
 // frontend/src/types/cookie.types.ts
+export interface CookieStats {
+  requests: number;
+  successes: number;
+  failures: number;
+  last_used: number | null;
+  last_error: string | any | null;
+  input_tokens: number;
+  output_tokens: number;
+}
+
+export interface AccountFlag {
+  type: string;
+  expires_at: number;
+}
+
+export interface AccountInfo {
+  email: string;
+  tier: "free" | "pro" | "enterprise" | "max" | null;
+  org_uuid: string | null;
+  flags: AccountFlag[];
+  updated_at: number;
+}
+
 export interface CookieStatus {
   cookie: string;
   reset_time: number | null;
+  stats?: CookieStats;
+  weight?: number;
+  capabilities?: string[];
+  groups?: string[];
+  account?: AccountInfo | null;
 }
 
 export interface UselessCookie {
   cookie: string;
   reason: string | any;
+  stats?: CookieStats;
+  account?: AccountInfo | null;
 }
 
 export interface CookieStatusInfo {
@@ -1,11 +1,29 @@
 // frontend/src/types/key.types.ts
+export interface KeyStats {
+  date: string;
+  requests: number;
+  prompt_tokens: number;
+  candidate_tokens: number;
+  thought_tokens: number;
+}
+
 export interface KeyStatus {
   key: string;
   count_403: number;
+  reset_time?: number | null;
+  stats?: KeyStats;
+}
+
+export interface UselessKey {
+  key: string;
+  count_403: number;
+  reason: string;
 }
 
 export interface KeyStatusInfo {
   valid: KeyStatus[];
+  exhausted: KeyStatus[];
+  invalid: UselessKey[];
 }
 
 export interface KeyFormState {
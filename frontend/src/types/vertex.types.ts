@@ -0,0 +1,15 @@
+// frontend/src/types/vertex.types.ts
+export interface VertexCredentialInfo {
+  client_email: string;
+  project_id: string | null;
+  private_key_id: string | null;
+  model_id: string | null;
+  location: string | null;
+  count_failure: number;
+  reset_time: number | null;
+}
+
+export interface VertexStatusInfo {
+  valid: VertexCredentialInfo[];
+  exhausted: VertexCredentialInfo[];
+}
@@ -1,4 +1,4 @@
-use axum::{Extension, extract::State, response::Response};
+use axum::{Extension, extract::State, http::HeaderMap, response::Response};
 use colored::Colorize;
 use scopeguard::defer;
 use tracing::info;
@@ -6,7 +6,11 @@ use tracing::info;
 use crate::{
     claude_state::{ClaudeApiFormat, ClaudeState},
     error::ClewdrError,
-    middleware::claude::{ClaudeContext, ClaudePreprocess},
+    middleware::{
+        KeyGroups,
+        claude::{ClaudeContext, ClaudePreprocess},
+    },
+    services::conversation::header_fingerprint,
     utils::{enabled, print_out_json},
 };
 /// Axum handler for the API messages
@@ -16,12 +20,16 @@ use crate::{
 /// # Arguments
 /// * `XApiKey(_)` - API key authentication
 /// * `state` - Application state containing client information
+/// * `groups` - Cookie groups of the user API key
+/// * `headers` - Request headers, which may identify the conversation
 /// * `p` - Request body containing messages and configuration
 ///
 /// # Returns
 /// * `Response` - Stream or JSON response from Claude
 pub async fn api_claude(
     State(mut state): State<ClaudeState>,
+    groups: Option<Extension<KeyGroups>>,
+    headers: HeaderMap,
     ClaudePreprocess(p, f): ClaudePreprocess,
 ) -> (Extension<ClaudeContext>, Result<Response, ClewdrError>) {
     // Check if the request is a test message
@@ -29,6 +37,8 @@ pub async fn api_claude(
     print_out_json(&p, "client_req.json");
     state.api_format = f.api_format;
     state.stream = stream;
+    state.groups = groups.and_then(|Extension(KeyGroups(g))| g);
+    state.fingerprint = header_fingerprint(&headers);
     let format_display = match f.api_format {
         ClaudeApiFormat::Claude => f.api_format.to_string().green(),
         ClaudeApiFormat::OpenAI => f.api_format.to_string().yellow(),
@@ -38,7 +48,7 @@ pub async fn api_claude(
         enabled(stream),
         p.messages.len().to_string().green(),
         p.model.green(),
-        enabled(p.thinking.is_some()),
+        enabled(p.thinking_enabled()),
         format_display
     );
     let stopwatch = chrono::Utc::now();
@@ -38,7 +38,9 @@ pub async fn api_get_config(
         obj.remove("cookie_array");
         obj.remove("wasted_cookie");
         obj.remove("gemini_keys");
-        obj["vertex"]["credential"] = "placeholder".into();
+        if obj["vertex"]["credential"].is_object() {
+            obj["vertex"]["credential"] = "placeholder".into();
+        }
     }
 
     Ok(Json(config_json))
@@ -1,15 +1,27 @@
-use axum::{Json, extract::State};
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
 use axum_auth::AuthBearer;
+use futures::{StreamExt, stream};
 use rquest::StatusCode;
+use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use std::fmt::Write;
 use tracing::{error, info, warn};
 
 use crate::{
     VERSION_INFO,
-    config::{CLEWDR_CONFIG, CookieStatus, KeyStatus},
+    claude_state::ClaudeState,
+    config::{CLEWDR_CONFIG, ClewdrCookie, CookieStatus, KeyStatus, VertexCredential},
     services::{
-        cookie_manager::{CookieEventSender, CookieStatusInfo},
+        cookie_manager::{CookieEventSender, CookieStatusInfo, ImportStatus},
+        health_check::{CookieHealthChecker, RevalidateResult},
         key_manager::{KeyEventSender, KeyStatusInfo},
+        probe::{ProbeResult, probe_cookie, probe_key},
+        vertex_manager::{VertexEventSender, VertexStatusInfo},
     },
 };
 
@@ -22,55 +34,488 @@ use crate::{
 /// * `c` - Cookie status to be submitted
 ///
 /// # Returns
-/// * `StatusCode` - HTTP status code indicating success or failure
+/// * `Response` - HTTP status code indicating success or failure,
+///   with the probe result if the cookie failed the probe on submit
 pub async fn api_post_cookie(
     State(s): State<CookieEventSender>,
     AuthBearer(t): AuthBearer,
-    Json(mut c): Json<CookieStatus>,
-) -> StatusCode {
+    Json(c): Json<CookieStatus>,
+) -> Response {
     if !CLEWDR_CONFIG.load().admin_auth(&t) {
-        return StatusCode::UNAUTHORIZED;
+        return StatusCode::UNAUTHORIZED.into_response();
     }
     if !c.cookie.validate() {
         warn!("Invalid cookie: {}", c.cookie);
-        return StatusCode::BAD_REQUEST;
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    let mut c = c.submitted();
+    if CLEWDR_CONFIG.load().probe_on_submit {
+        let (result, probed) = probe_cookie(ClaudeState::new(s.to_owned()), c).await;
+        if !result.accepted() {
+            warn!("Cookie rejected by probe: {}", probed.cookie);
+            return (StatusCode::BAD_REQUEST, Json(result)).into_response();
+        }
+        c = probed;
     }
-    c.reset_time = None;
     info!("Cookie accepted: {}", c.cookie);
     match s.submit(c).await {
         Ok(_) => {
             info!("Cookie submitted successfully");
-            StatusCode::OK
+            StatusCode::OK.into_response()
         }
         Err(e) => {
             error!("Failed to submit cookie: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
 }
 
+/// API endpoint to probe a cookie without adding it
+/// Bootstraps the cookie against Claude and returns the diagnosis
+///
+/// # Arguments
+/// * `s` - Application state containing event sender
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - Cookie to probe
+///
+/// # Returns
+/// * `Result<Json<ProbeResult>, StatusCode>` - Diagnosis of the cookie or error status
+pub async fn api_test_cookie(
+    State(s): State<CookieEventSender>,
+    AuthBearer(t): AuthBearer,
+    Json(c): Json<CookieStatus>,
+) -> Result<Json<ProbeResult>, StatusCode> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    if !c.cookie.validate() {
+        warn!("Invalid cookie: {}", c.cookie);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (result, _) = probe_cookie(ClaudeState::new(s), c).await;
+    Ok(Json(result))
+}
+
 pub async fn api_post_key(
     State(s): State<KeyEventSender>,
     AuthBearer(t): AuthBearer,
-    Json(c): Json<KeyStatus>,
-) -> StatusCode {
+    Json(mut c): Json<KeyStatus>,
+) -> Response {
     if !CLEWDR_CONFIG.load().admin_auth(&t) {
-        return StatusCode::UNAUTHORIZED;
+        return StatusCode::UNAUTHORIZED.into_response();
     }
     if !c.key.validate() {
         warn!("Invalid key: {}", c.key);
-        return StatusCode::BAD_REQUEST;
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+    if CLEWDR_CONFIG.load().probe_on_submit {
+        let result = probe_key(&c.key).await;
+        if !result.accepted() {
+            warn!("Key rejected by probe: {}", c.key);
+            return (StatusCode::BAD_REQUEST, Json(result)).into_response();
+        }
+        // a rate limited key waits for its reset time in the pool
+        c.reset_time = result.reset_time;
     }
     info!("Key accepted: {}", c.key);
     match s.submit(c).await {
         Ok(_) => {
             info!("Key submitted successfully");
-            StatusCode::OK
+            StatusCode::OK.into_response()
         }
         Err(e) => {
             error!("Failed to submit key: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// API endpoint to probe a Gemini key without adding it
+/// Lists the models visible to the key and returns the diagnosis
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - Key to probe
+///
+/// # Returns
+/// * `Result<Json<ProbeResult>, StatusCode>` - Diagnosis of the key or error status
+pub async fn api_test_key(
+    AuthBearer(t): AuthBearer,
+    Json(c): Json<KeyStatus>,
+) -> Result<Json<ProbeResult>, StatusCode> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    if !c.key.validate() {
+        warn!("Invalid key: {}", c.key);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(probe_key(&c.key).await))
+}
+
+/// A single entry of a bulk cookie import
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BulkCookie {
+    Raw(String),
+    Status(Box<CookieStatus>),
+}
+
+/// Outcome of importing a single entry
+#[derive(Serialize)]
+pub struct BulkCookieResult {
+    pub cookie: String,
+    pub status: ImportStatus,
+    /// Result of the probe on submit, if the entry was probed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe: Option<ProbeResult>,
+}
+
+/// API endpoint to submit multiple cookies at once
+/// Accepts a JSON array of cookies or cookie objects, or newline separated text,
+/// empty lines and lines starting with '#' are skipped
+/// Entries are probed first if probe on submit is enabled, rejected ones are not imported
+/// and rate limited ones are imported with their reset time
+///
+/// # Arguments
+/// * `s` - Application state containing event sender
+/// * `t` - Auth bearer token for admin authentication
+/// * `headers` - Request headers, used to detect JSON bodies
+/// * `body` - The cookies to import
+///
+/// # Returns
+/// * `Result<Json<Value>, (StatusCode, Json<serde_json::Value>)>` - Outcome of each entry or error
+pub async fn api_post_cookies_bulk(
+    State(s): State<CookieEventSender>,
+    AuthBearer(t): AuthBearer,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<Value>, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+    let is_json = headers
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.contains("json"))
+        || body.trim_start().starts_with('[');
+    let entries = if is_json {
+        serde_json::from_str::<Vec<BulkCookie>>(&body)
+            .map_err(|e| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": format!("Invalid JSON array: {}", e)
+                    })),
+                )
+            })?
+            .into_iter()
+            .map(|c| match c {
+                BulkCookie::Raw(c) => CookieStatus::new(c.trim(), None),
+                BulkCookie::Status(c) => *c,
+            })
+            .collect::<Vec<_>>()
+    } else {
+        body.lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| CookieStatus::new(l, None))
+            .collect()
+    };
+    let entries = entries
+        .into_iter()
+        .map(CookieStatus::submitted)
+        .collect::<Vec<_>>();
+    let cookies = entries
+        .iter()
+        .map(|c| c.cookie.to_string())
+        .collect::<Vec<_>>();
+    let probed = if CLEWDR_CONFIG.load().probe_on_submit {
+        let concurrency = CLEWDR_CONFIG.load().health_check_concurrency.max(1);
+        stream::iter(entries)
+            .map(|c| {
+                let s = s.to_owned();
+                async move {
+                    // malformed entries are reported by the import without a probe
+                    if !c.cookie.validate() {
+                        return (None, c);
+                    }
+                    let (result, c) = probe_cookie(ClaudeState::new(s), c).await;
+                    (Some(result), c)
+                }
+            })
+            .buffered(concurrency)
+            .collect::<Vec<_>>()
+            .await
+    } else {
+        entries.into_iter().map(|c| (None, c)).collect()
+    };
+    let (probes, entries): (Vec<_>, Vec<_>) = probed.into_iter().unzip();
+    let rejected = |probe: &Option<ProbeResult>| probe.as_ref().is_some_and(|p| !p.accepted());
+    let entries = entries
+        .into_iter()
+        .zip(&probes)
+        .filter(|(_, probe)| !rejected(probe))
+        .map(|(c, _)| c)
+        .collect::<Vec<_>>();
+    let statuses = s.import(entries).await.map_err(|e| {
+        error!("Failed to import cookies: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({
+                "error": format!("Failed to import cookies: {}", e)
+            })),
+        )
+    })?;
+    // statuses only cover the entries that passed the probe, in order
+    let mut statuses = statuses.into_iter();
+    let results = cookies
+        .into_iter()
+        .zip(probes)
+        .filter_map(|(cookie, probe)| {
+            let status = if rejected(&probe) {
+                ImportStatus::Rejected
+            } else {
+                statuses.next()?
+            };
+            Some(BulkCookieResult {
+                cookie,
+                status,
+                probe,
+            })
+        })
+        .collect::<Vec<_>>();
+    let accepted = results
+        .iter()
+        .filter(|r| r.status == ImportStatus::Accepted)
+        .count();
+    info!("Imported {} of {} cookies", accepted, results.len());
+    Ok(Json(json!({
+        "accepted": accepted,
+        "results": results,
+    })))
+}
+
+/// Request body of the cookie groups endpoint
+#[derive(Deserialize)]
+pub struct CookieGroups {
+    pub cookie: ClewdrCookie,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// API endpoint to set the groups of a cookie
+/// Only user API keys mapped to one of the groups can use the cookie,
+/// the main password can use every cookie
+///
+/// # Arguments
+/// * `s` - Application state containing event sender
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - The cookie and its new groups
+///
+/// # Returns
+/// * `Result<StatusCode, (StatusCode, Json<serde_json::Value>)>` - Success status or error
+pub async fn api_set_cookie_groups(
+    State(s): State<CookieEventSender>,
+    AuthBearer(t): AuthBearer,
+    Json(c): Json<CookieGroups>,
+) -> Result<StatusCode, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+    match s.set_groups(c.cookie.to_owned(), c.groups).await {
+        Ok(_) => {
+            info!("Cookie groups updated: {}", c.cookie.ellipse());
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(e) => {
+            error!("Failed to set cookie groups: {}", e);
+            Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "error": format!("Failed to set cookie groups: {}", e)
+                })),
+            ))
+        }
+    }
+}
+
+/// Request body of the cookie weight endpoint
+#[derive(Deserialize)]
+pub struct CookieWeight {
+    pub cookie: ClewdrCookie,
+    pub weight: u32,
+}
+
+/// API endpoint to set the dispatch weight of a cookie
+/// The weight is used by the random weighted dispatch strategy,
+/// a cookie with weight 0 is never picked by it
+///
+/// # Arguments
+/// * `s` - Application state containing event sender
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - The cookie and its new weight
+///
+/// # Returns
+/// * `Result<StatusCode, (StatusCode, Json<serde_json::Value>)>` - Success status or error
+pub async fn api_set_cookie_weight(
+    State(s): State<CookieEventSender>,
+    AuthBearer(t): AuthBearer,
+    Json(c): Json<CookieWeight>,
+) -> Result<StatusCode, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+    match s.set_weight(c.cookie.to_owned(), c.weight).await {
+        Ok(_) => {
+            info!("Cookie weight updated: {}", c.cookie.ellipse());
+            Ok(StatusCode::NO_CONTENT)
         }
+        Err(e) => {
+            error!("Failed to set cookie weight: {}", e);
+            Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "error": format!("Failed to set cookie weight: {}", e)
+                })),
+            ))
+        }
+    }
+}
+
+/// Query parameters of the cookie export endpoint
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    /// "json" or "text", defaults to text
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+/// API endpoint to export all cookies
+/// Returns the valid, exhausted and invalid cookies as JSON,
+/// or as text with one cookie per line under a '#' header for each collection
+///
+/// # Arguments
+/// * `s` - Application state containing event sender
+/// * `t` - Auth bearer token for admin authentication
+/// * `q` - Export format
+///
+/// # Returns
+/// * `Response` - The exported cookies or error
+pub async fn api_export_cookies(
+    State(s): State<CookieEventSender>,
+    AuthBearer(t): AuthBearer,
+    Query(q): Query<ExportQuery>,
+) -> Response {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "Unauthorized"
+            })),
+        )
+            .into_response();
+    }
+    let status = match s.get_status().await {
+        Ok(status) => status,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": format!("Failed to get cookie status: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+    if q.format.as_deref() == Some("json") {
+        return Json(status).into_response();
+    }
+    let mut text = String::new();
+    let _ = writeln!(text, "# valid");
+    status.valid.iter().for_each(|c| {
+        let _ = writeln!(text, "{}", c.cookie);
+    });
+    let _ = writeln!(text, "# exhausted");
+    status.exhausted.iter().for_each(|c| {
+        let _ = writeln!(text, "{}", c.cookie);
+    });
+    let _ = writeln!(text, "# invalid");
+    status.invalid.iter().for_each(|c| {
+        let _ = writeln!(text, "{}", c.cookie);
+    });
+    ([(CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response()
+}
+
+/// API endpoint to trigger a health check of all cookies
+/// The check runs in the background, results are applied to the cookie manager
+///
+/// # Arguments
+/// * `s` - Health checker handle
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `StatusCode` - ACCEPTED if the check started, CONFLICT if a check is already running
+pub async fn api_check_cookies(
+    State(s): State<CookieHealthChecker>,
+    AuthBearer(t): AuthBearer,
+) -> StatusCode {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    if s.trigger() {
+        info!("Cookie health check triggered");
+        StatusCode::ACCEPTED
+    } else {
+        warn!("Cookie health check already running");
+        StatusCode::CONFLICT
+    }
+}
+
+/// API endpoint to revalidate all invalid cookies
+/// Bootstraps each invalid cookie again and restores the ones that pass
+///
+/// # Arguments
+/// * `s` - Health checker handle
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<Vec<RevalidateResult>>, (StatusCode, Json<serde_json::Value>)>` - Result of each cookie or error
+pub async fn api_revalidate_cookies(
+    State(s): State<CookieHealthChecker>,
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Vec<RevalidateResult>>, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+    match s.revalidate().await {
+        Some(results) => Ok(Json(results)),
+        None => Err((
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": "Revalidation already running"
+            })),
+        )),
     }
 }
 
@@ -223,6 +668,116 @@ pub async fn api_delete_key(
     }
 }
 
+/// API endpoint to add a Vertex credential to the pool
+/// Submitting a credential that is already in the pool updates its overrides
+///
+/// # Arguments
+/// * `s` - Event sender of the Vertex manager
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - The service account key with optional `model_id` and `location`
+///
+/// # Returns
+/// * `StatusCode` - HTTP status code indicating success or failure
+pub async fn api_post_vertex(
+    State(s): State<VertexEventSender>,
+    AuthBearer(t): AuthBearer,
+    Json(c): Json<VertexCredential>,
+) -> StatusCode {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return StatusCode::UNAUTHORIZED;
+    }
+    if c.id().is_empty() || c.credential.private_key.is_empty() {
+        warn!("Invalid Vertex credential");
+        return StatusCode::BAD_REQUEST;
+    }
+    info!("Vertex credential accepted: {}", c.id());
+    match s.submit(c).await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Failed to submit Vertex credential: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// API endpoint to list the Vertex credentials, without their private keys
+///
+/// # Arguments
+/// * `s` - Event sender of the Vertex manager
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<VertexStatusInfo>, (StatusCode, Json<serde_json::Value>)>` - Credential status on success
+pub async fn api_get_vertex(
+    State(s): State<VertexEventSender>,
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<VertexStatusInfo>, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+
+    match s.get_status().await {
+        Ok(status) => Ok(Json(status)),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to get Vertex status: {}", e)
+            })),
+        )),
+    }
+}
+
+/// Identifies a Vertex credential to delete
+#[derive(Deserialize)]
+pub struct VertexDeleteRequest {
+    pub client_email: String,
+}
+
+/// API endpoint to remove a Vertex credential from the pool
+///
+/// # Arguments
+/// * `s` - Event sender of the Vertex manager
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - Client email of the credential to delete
+///
+/// # Returns
+/// * `Result<StatusCode, (StatusCode, Json<serde_json::Value>)>` - No content on success
+pub async fn api_delete_vertex(
+    State(s): State<VertexEventSender>,
+    AuthBearer(t): AuthBearer,
+    Json(c): Json<VertexDeleteRequest>,
+) -> Result<StatusCode, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+
+    match s.delete_credential(c.client_email.to_owned()).await {
+        Ok(_) => {
+            info!("Vertex credential deleted successfully: {}", c.client_email);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(e) => {
+            error!("Failed to delete Vertex credential: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": format!("Failed to delete Vertex credential: {}", e)
+                })),
+            ))
+        }
+    }
+}
+
 /// API endpoint to get the application version information
 ///
 /// # Returns
@@ -15,6 +15,8 @@ pub use config::{api_get_config, api_post_config};
 pub use gemini::{api_post_gemini, api_post_gemini_oai};
 /// Miscellaneous endpoints for authentication, cookies, and version information
 pub use misc::{
-    api_auth, api_delete_cookie, api_delete_key, api_get_cookies, api_get_keys, api_get_models,
-    api_post_cookie, api_post_key, api_version,
+    api_auth, api_check_cookies, api_delete_cookie, api_delete_key, api_delete_vertex,
+    api_export_cookies, api_get_cookies, api_get_keys, api_get_models, api_get_vertex,
+    api_post_cookie, api_post_cookies_bulk, api_post_key, api_post_vertex, api_revalidate_cookies,
+    api_set_cookie_groups, api_set_cookie_weight, api_test_cookie, api_test_key, api_version,
 };
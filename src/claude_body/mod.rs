@@ -1,9 +1,10 @@
 pub mod request;
 pub mod response;
+pub mod tool_use;
 
 use serde::{Deserialize, Serialize};
 
-use crate::types::claude_message::ImageSource;
+use crate::{types::claude_message::ImageSource, utils::count_tokens};
 
 /// Claude.ai attachment
 #[derive(Deserialize, Serialize, Debug)]
@@ -46,6 +47,20 @@ pub struct RequestBody {
     #[serde(skip)]
     pub images: Vec<ImageSource>,
     pub tools: Vec<Tool>,
+    /// Message the prompt replies to, when continuing a conversation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_message_uuid: Option<String>,
+}
+
+impl RequestBody {
+    /// Counts the approximate number of input tokens in the prompt and attachments
+    pub fn count_tokens(&self) -> u64 {
+        self.attachments
+            .iter()
+            .map(|a| count_tokens(&a.extracted_content))
+            .sum::<u64>()
+            + count_tokens(&self.prompt)
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
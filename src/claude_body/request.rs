@@ -11,7 +11,10 @@ use std::{fmt::Write, mem};
 use tracing::warn;
 
 use crate::{
-    claude_body::{Attachment, RequestBody, Tool},
+    claude_body::{
+        Attachment, RequestBody, Tool,
+        tool_use::{render_tool_result, render_tool_use, render_tools},
+    },
     claude_state::{ClaudeApiFormat, ClaudeState},
     config::CLEWDR_CONFIG,
     types::claude_message::{
@@ -30,11 +33,22 @@ struct Merged {
 
 impl ClaudeState {
     pub fn transform_request(&self, mut value: CreateMessageParams) -> Option<RequestBody> {
+        // tool definitions are part of the system instructions
+        let tools = value.uses_tools().then(|| {
+            render_tools(
+                value.tools.as_deref().unwrap_or_default(),
+                value.tool_choice.as_ref(),
+            )
+        });
         let (value, merged) = match self.api_format {
             ClaudeApiFormat::Claude => {
                 let system = value.system.take();
                 let msgs = mem::take(&mut value.messages);
                 let system = merge_system(system.unwrap_or_default());
+                let system = match tools {
+                    Some(tools) => format!("{}\n\n{}", system, tools),
+                    None => system,
+                };
                 let merged = merge_messages(msgs, system)?;
                 (value, merged)
             }
@@ -48,7 +62,7 @@ impl ClaudeState {
                         msg.role = role;
                     }
                 }
-                let merged = merge_messages(msgs, String::new())?;
+                let merged = merge_messages(msgs, tools.unwrap_or_default())?;
                 (value, merged)
             }
         };
@@ -65,15 +79,64 @@ impl ClaudeState {
             } else {
                 None
             },
-            rendering_mode: if value.stream.unwrap_or_default() {
-                "messages".to_string()
-            } else {
-                "raw".to_string()
-            },
+            // non-stream responses are collected from the events as well
+            rendering_mode: "messages".to_string(),
             prompt: merged.prompt,
             timezone: TIME_ZONE.to_string(),
             images: merged.images,
             tools,
+            parent_message_uuid: None,
+        })
+    }
+
+    /// Transforms the new user messages of a continued conversation into a request body
+    ///
+    /// Only the new turn is sent as the prompt, the history is already part of the
+    /// Claude.ai conversation.
+    ///
+    /// # Arguments
+    /// * `value` - The client request
+    /// * `turn` - The user messages not sent to the conversation yet
+    /// * `parent` - UUID of the message the turn replies to
+    ///
+    /// # Returns
+    /// * `Option<RequestBody>` - The request body, or None if the turn is empty
+    pub fn transform_turn(
+        &self,
+        value: &CreateMessageParams,
+        turn: &[Message],
+        parent: String,
+    ) -> Option<RequestBody> {
+        let mut images = vec![];
+        let prompt = turn
+            .iter()
+            .map(|m| render_content(m.content.to_owned(), &mut images))
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if prompt.is_empty() && images.is_empty() {
+            return None;
+        }
+        print_out_text(prompt.as_str(), "turn.txt");
+        let mut tools = vec![];
+        if CLEWDR_CONFIG.load().web_search {
+            tools.push(Tool::web_search());
+        }
+        Some(RequestBody {
+            max_tokens_to_sample: value.max_tokens,
+            attachments: vec![],
+            files: vec![],
+            model: if self.is_pro() {
+                Some(value.model.to_owned())
+            } else {
+                None
+            },
+            rendering_mode: "messages".to_string(),
+            prompt,
+            timezone: TIME_ZONE.to_string(),
+            images,
+            tools,
+            parent_message_uuid: Some(parent),
         })
     }
 
@@ -179,43 +242,12 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
 
     let chunks = msgs
         .into_iter()
-        .filter_map(|m| match m.content {
-            MessageContent::Blocks { content } => {
-                // collect all text blocks, join them with new line
-                let blocks = content
-                    .into_iter()
-                    .filter_map(|b| match b {
-                        ContentBlock::Text { text } => Some(text.trim().to_string()),
-                        ContentBlock::Image { source } => {
-                            // push image to the list
-                            imgs.push(source);
-                            None
-                        }
-                        ContentBlock::ImageUrl { image_url } => {
-                            // oai image
-                            if let Some(source) = extract_image_from_url(&image_url.url) {
-                                imgs.push(source);
-                            }
-                            None
-                        }
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n");
-                if blocks.is_empty() {
-                    None
-                } else {
-                    Some((m.role, blocks))
-                }
-            }
-            MessageContent::Text { content } => {
-                // plain text
-                let content = content.trim().to_string();
-                if content.is_empty() {
-                    None
-                } else {
-                    Some((m.role, content))
-                }
+        .filter_map(|m| {
+            let text = render_content(m.content, &mut imgs);
+            if text.is_empty() {
+                None
+            } else {
+                Some((m.role, text))
             }
         })
         // chunk by role
@@ -255,6 +287,58 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
     })
 }
 
+/// Renders the content of a message as text, extracting its images
+///
+/// # Arguments
+/// * `content` - Content of the message
+/// * `imgs` - Images found in the content are pushed here
+///
+/// # Returns
+/// The text of the message, with tool calls and results rendered as text
+fn render_content(content: MessageContent, imgs: &mut Vec<ImageSource>) -> String {
+    match content {
+        MessageContent::Blocks { content } => {
+            // collect all text blocks, join them with new line
+            content
+                .into_iter()
+                .filter_map(|b| match b {
+                    ContentBlock::Text { text } => Some(text.trim().to_string()),
+                    ContentBlock::Image { source } => {
+                        // push image to the list
+                        imgs.push(source);
+                        None
+                    }
+                    ContentBlock::ImageUrl { image_url } => {
+                        // oai image
+                        if let Some(source) = extract_image_from_url(&image_url.url) {
+                            imgs.push(source);
+                        }
+                        None
+                    }
+                    ContentBlock::ToolUse { name, input, .. } => {
+                        Some(render_tool_use(&name, &input))
+                    }
+                    ContentBlock::ToolResult {
+                        tool_use_id,
+                        content,
+                        is_error,
+                    } => Some(render_tool_result(
+                        &tool_use_id,
+                        &content,
+                        is_error.unwrap_or_default(),
+                    )),
+                    // thinking of previous turns is not sent back
+                    ContentBlock::Thinking { .. } => None,
+                    ContentBlock::RedactedThinking { .. } => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        // plain text
+        MessageContent::Text { content } => content.trim().to_string(),
+    }
+}
+
 /// Generates random padding text of specified length
 /// Used to pad prompts with tokens to meet minimum length requirements
 ///
@@ -1,45 +1,458 @@
-use axum::{Json, body::Body, response::IntoResponse};
+use async_stream::{stream, try_stream};
+use axum::{
+    Json,
+    body::Body,
+    response::{IntoResponse, Sse, sse::Event},
+};
 use bytes::Bytes;
-use eventsource_stream::{EventStream, Eventsource};
-use futures::{Stream, TryStreamExt};
+use eventsource_stream::{EventStream, EventStreamError, Eventsource};
+use futures::{Stream, StreamExt, future::Either, stream};
 use itertools::Itertools;
-use serde::Deserialize;
+use serde_json::{Value, json};
+use snafu::ResultExt;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
+    claude_body::tool_use::{Segment, ToolCallParser, parse_tool_calls, tool_use_id},
     claude_state::ClaudeState,
-    services::cache::CACHE,
-    types::claude_message::{ContentBlock, Message, Role},
-    utils::print_out_text,
+    config::{CLEWDR_CONFIG, CookieUsage},
+    error::{ClewdrError, RquestSnafu},
+    services::{cache::CACHE, conversation::CONVERSATIONS},
+    types::claude_message::{
+        ContentBlock, ContentBlockDelta, CreateMessageResponse, Message, MessageDeltaContent, Role,
+        StopReason, StreamError, StreamEvent, StreamUsage, Usage,
+    },
+    utils::{count_tokens, print_out_json},
 };
 
-/// Merges server-sent events (SSE) from a stream into a single string
-/// Extracts and concatenates completion data from events
+/// Extracts the generated text from raw Claude.ai SSE bytes
+/// Handles both the `raw` (completion) and `messages` (content block delta) rendering modes
 ///
 /// # Arguments
-/// * `stream` - Event stream to process
+/// * `bytes` - Raw SSE bytes received from Claude.ai
 ///
 /// # Returns
-/// Combined completion text from all events
-pub async fn merge_sse(
-    stream: EventStream<impl Stream<Item = Result<Bytes, rquest::Error>>>,
-) -> String {
-    #[derive(Deserialize)]
-    struct Data {
-        completion: String,
-    }
-    stream
-        .try_collect::<Vec<_>>()
-        .await
-        .unwrap_or_default()
-        .into_iter()
-        .filter_map(|event| {
-            let data = event.data;
-            let data = serde_json::from_str::<Data>(&data).ok()?;
-            Some(data.completion)
+/// The concatenated generated text
+fn extract_completion(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .filter_map(|data| serde_json::from_str::<Value>(data.trim()).ok())
+        .filter_map(|v| {
+            if let Some(completion) = v["completion"].as_str() {
+                return Some(completion.to_string());
+            }
+            let delta = &v["delta"];
+            delta["text"]
+                .as_str()
+                .or(delta["thinking"].as_str())
+                .map(|t| t.to_string())
         })
         .join("")
 }
 
+/// Extracts the text generated for the client from raw Claude.ai SSE bytes
+/// Unlike [`extract_completion`], thinking is left out
+///
+/// # Arguments
+/// * `bytes` - Raw SSE bytes received from Claude.ai
+///
+/// # Returns
+/// The concatenated text deltas
+fn extract_reply(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .filter_map(|data| serde_json::from_str::<StreamEvent>(data.trim()).ok())
+        .filter_map(|event| match event {
+            StreamEvent::ContentBlockDelta {
+                delta: ContentBlockDelta::TextDelta { text },
+                ..
+            } => Some(text),
+            _ => None,
+        })
+        .join("")
+}
+
+/// Progress of a Claude.ai response, checked before it is forwarded to the client
+#[derive(Debug, Default)]
+struct ResponseCheck {
+    /// Bytes of the line being received
+    line: Vec<u8>,
+    /// Whether the model generated any content
+    content: bool,
+    /// Whether the message was completed
+    stopped: bool,
+}
+
+impl ResponseCheck {
+    /// Feeds raw SSE bytes to the check
+    ///
+    /// # Arguments
+    /// * `bytes` - The next bytes of the response
+    ///
+    /// # Returns
+    /// * `Result<(), ClewdrError>` - Error if Claude.ai reported an error in the stream
+    fn push(&mut self, bytes: &[u8]) -> Result<(), ClewdrError> {
+        self.line.extend_from_slice(bytes);
+        while let Some(pos) = self.line.iter().position(|&b| b == b'\n') {
+            let line = self.line.drain(..=pos).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:") else {
+                continue;
+            };
+            let Ok(event) = serde_json::from_str::<StreamEvent>(data.trim()) else {
+                continue;
+            };
+            match event {
+                StreamEvent::ContentBlockStart {
+                    content_block: ContentBlock::ToolUse { .. },
+                    ..
+                } => self.content = true,
+                StreamEvent::ContentBlockDelta {
+                    delta:
+                        ContentBlockDelta::TextDelta { text }
+                        | ContentBlockDelta::ThinkingDelta { thinking: text }
+                        | ContentBlockDelta::InputJsonDelta { partial_json: text },
+                    ..
+                } if !text.is_empty() => self.content = true,
+                StreamEvent::MessageStop => self.stopped = true,
+                StreamEvent::Error { error } => {
+                    return Err(ClewdrError::UpstreamStreamError {
+                        type_: error.type_,
+                        message: error.message,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds the error event sent when the stream of Claude.ai ends before the message is complete
+fn truncated_event() -> StreamEvent {
+    StreamEvent::Error {
+        error: StreamError {
+            type_: "api_error".to_string(),
+            message: "The response was cut off by Claude.ai".to_string(),
+        },
+    }
+}
+
+/// Builds an SSE event from a Claude stream event, named after its type
+///
+/// # Arguments
+/// * `event` - The stream event
+///
+/// # Returns
+/// The SSE event to send to the client
+fn sse_event(event: StreamEvent) -> Event {
+    let data = serde_json::to_value(event).unwrap_or_default();
+    Event::default()
+        .event(data["type"].as_str().unwrap_or_default())
+        .json_data(data)
+        .unwrap_or_default()
+}
+
+/// Passes an event that is not a Claude stream event through unchanged
+fn raw_event(event: eventsource_stream::Event) -> Event {
+    let eventsource_stream::Event {
+        data,
+        id,
+        event,
+        retry,
+    } = event;
+    let event = Event::default().event(event).id(id).data(&data);
+    match retry {
+        Some(retry) => event.retry(retry),
+        None => event,
+    }
+}
+
+/// Estimates the usage of a streamed message, since Claude.ai does not report it
+struct UsageCounter {
+    input_tokens: u32,
+    output: String,
+}
+
+impl UsageCounter {
+    fn new(input_tokens: u64) -> Self {
+        Self {
+            input_tokens: input_tokens as u32,
+            output: String::new(),
+        }
+    }
+
+    /// Records the generated content of an event and fills in its usage
+    ///
+    /// # Arguments
+    /// * `event` - The stream event sent to the client
+    fn count(&mut self, event: &mut StreamEvent) {
+        match event {
+            StreamEvent::MessageStart { message } => {
+                message.usage = Usage {
+                    input_tokens: self.input_tokens,
+                    output_tokens: 0,
+                };
+            }
+            StreamEvent::ContentBlockDelta { delta, .. } => match delta {
+                ContentBlockDelta::TextDelta { text } => self.output.push_str(text),
+                ContentBlockDelta::ThinkingDelta { thinking } => self.output.push_str(thinking),
+                ContentBlockDelta::InputJsonDelta { partial_json } => {
+                    self.output.push_str(partial_json)
+                }
+                _ => {}
+            },
+            StreamEvent::MessageDelta { usage, .. } => {
+                *usage = Some(StreamUsage {
+                    input_tokens: self.input_tokens,
+                    output_tokens: count_tokens(&self.output) as u32,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Fills in the usage of a Claude.ai event stream
+///
+/// # Arguments
+/// * `stream` - The Claude.ai event stream
+/// * `input_tokens` - Number of tokens in the prompt
+///
+/// # Returns
+/// A stream of SSE events in Claude API format
+fn usage_stream<S>(
+    stream: EventStream<S>,
+    input_tokens: u64,
+) -> impl Stream<Item = Result<Event, EventStreamError<rquest::Error>>> + Send
+where
+    S: Stream<Item = Result<Bytes, rquest::Error>> + Send,
+{
+    try_stream! {
+        let mut counter = UsageCounter::new(input_tokens);
+        let mut finished = false;
+        let mut stopped = false;
+        for await event in stream {
+            let event = event?;
+            let Ok(mut parsed) = serde_json::from_str::<StreamEvent>(&event.data) else {
+                yield raw_event(event);
+                continue;
+            };
+            match parsed {
+                StreamEvent::MessageDelta { .. } => finished = true,
+                // the usage is reported in the message delta
+                StreamEvent::MessageStop if !finished => {
+                    finished = true;
+                    let mut delta = StreamEvent::MessageDelta {
+                        delta: MessageDeltaContent {
+                            stop_reason: Some(StopReason::EndTurn),
+                            stop_sequence: None,
+                        },
+                        usage: None,
+                    };
+                    counter.count(&mut delta);
+                    yield sse_event(delta);
+                }
+                _ => {}
+            }
+            if matches!(parsed, StreamEvent::MessageStop | StreamEvent::Error { .. }) {
+                stopped = true;
+            }
+            counter.count(&mut parsed);
+            yield sse_event(parsed);
+        }
+        if !stopped {
+            yield sse_event(truncated_event());
+        }
+    }
+}
+
+/// Turns parsed segments of the model output into content block events
+///
+/// # Arguments
+/// * `segments` - Text and tool calls parsed from the output
+/// * `text_index` - Index of the text block currently open, if any
+/// * `next` - Index of the next content block
+///
+/// # Returns
+/// Stream events describing the segments
+fn segment_events(
+    segments: Vec<Segment>,
+    text_index: &mut Option<usize>,
+    next: &mut usize,
+) -> Vec<StreamEvent> {
+    let mut events = vec![];
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => {
+                let index = *text_index.get_or_insert_with(|| {
+                    events.push(StreamEvent::ContentBlockStart {
+                        index: *next,
+                        content_block: ContentBlock::text(""),
+                    });
+                    *next += 1;
+                    *next - 1
+                });
+                events.push(StreamEvent::ContentBlockDelta {
+                    index,
+                    delta: ContentBlockDelta::TextDelta { text },
+                });
+            }
+            Segment::ToolCall { name, input } => {
+                if let Some(index) = text_index.take() {
+                    events.push(StreamEvent::ContentBlockStop { index });
+                }
+                let index = *next;
+                *next += 1;
+                events.push(StreamEvent::ContentBlockStart {
+                    index,
+                    content_block: ContentBlock::ToolUse {
+                        id: tool_use_id(),
+                        name,
+                        input: json!({}),
+                    },
+                });
+                events.push(StreamEvent::ContentBlockDelta {
+                    index,
+                    delta: ContentBlockDelta::InputJsonDelta {
+                        partial_json: input.to_string(),
+                    },
+                });
+                events.push(StreamEvent::ContentBlockStop { index });
+            }
+        }
+    }
+    events
+}
+
+/// Rewrites the text of a Claude.ai event stream into text and tool use blocks
+///
+/// Text blocks of Claude.ai are run through a [`ToolCallParser`], other blocks are
+/// passed through with their indices shifted. If the model called a tool, the
+/// stop reason of the message is set to `tool_use`.
+///
+/// # Arguments
+/// * `stream` - The Claude.ai event stream
+/// * `input_tokens` - Number of tokens in the prompt
+///
+/// # Returns
+/// A stream of SSE events in Claude API format
+fn tool_use_stream<S>(
+    stream: EventStream<S>,
+    input_tokens: u64,
+) -> impl Stream<Item = Result<Event, EventStreamError<rquest::Error>>> + Send
+where
+    S: Stream<Item = Result<Bytes, rquest::Error>> + Send,
+{
+    try_stream! {
+        let mut parser = ToolCallParser::default();
+        let mut counter = UsageCounter::new(input_tokens);
+        // indices of Claude.ai text blocks
+        let mut text_blocks = HashSet::new();
+        // Claude.ai indices of other blocks mapped to the indices sent to the client
+        let mut blocks = HashMap::new();
+        let mut text_index = None;
+        let mut next = 0;
+        let mut finished = false;
+        let mut stopped = false;
+        for await event in stream {
+            let event = event?;
+            let Ok(parsed) = serde_json::from_str::<StreamEvent>(&event.data) else {
+                yield raw_event(event);
+                continue;
+            };
+            let events = match parsed {
+                StreamEvent::ContentBlockStart {
+                    index,
+                    content_block: ContentBlock::Text { .. },
+                } => {
+                    text_blocks.insert(index);
+                    vec![]
+                }
+                StreamEvent::ContentBlockStart {
+                    index,
+                    content_block,
+                } => {
+                    let mut events = vec![];
+                    if let Some(index) = text_index.take() {
+                        events.push(StreamEvent::ContentBlockStop { index });
+                    }
+                    blocks.insert(index, next);
+                    events.push(StreamEvent::ContentBlockStart {
+                        index: next,
+                        content_block,
+                    });
+                    next += 1;
+                    events
+                }
+                StreamEvent::ContentBlockDelta {
+                    index,
+                    delta: ContentBlockDelta::TextDelta { text },
+                } if text_blocks.contains(&index) => {
+                    segment_events(parser.push(&text), &mut text_index, &mut next)
+                }
+                StreamEvent::ContentBlockDelta { index, delta } => {
+                    let index = blocks.get(&index).copied().unwrap_or(index);
+                    vec![StreamEvent::ContentBlockDelta { index, delta }]
+                }
+                // text blocks are closed when a tool is called or the message ends
+                StreamEvent::ContentBlockStop { index } if text_blocks.contains(&index) => vec![],
+                StreamEvent::ContentBlockStop { index } => {
+                    let index = blocks.get(&index).copied().unwrap_or(index);
+                    vec![StreamEvent::ContentBlockStop { index }]
+                }
+                StreamEvent::MessageDelta { mut delta, usage } if !finished => {
+                    finished = true;
+                    let mut events = segment_events(parser.finish(), &mut text_index, &mut next);
+                    if let Some(index) = text_index.take() {
+                        events.push(StreamEvent::ContentBlockStop { index });
+                    }
+                    if parser.called() {
+                        delta.stop_reason = Some(StopReason::ToolUse);
+                        delta.stop_sequence = None;
+                    }
+                    events.push(StreamEvent::MessageDelta { delta, usage });
+                    events
+                }
+                StreamEvent::MessageStop if !finished => {
+                    finished = true;
+                    let mut events = segment_events(parser.finish(), &mut text_index, &mut next);
+                    if let Some(index) = text_index.take() {
+                        events.push(StreamEvent::ContentBlockStop { index });
+                    }
+                    let stop_reason = if parser.called() {
+                        StopReason::ToolUse
+                    } else {
+                        StopReason::EndTurn
+                    };
+                    events.push(StreamEvent::MessageDelta {
+                        delta: MessageDeltaContent {
+                            stop_reason: Some(stop_reason),
+                            stop_sequence: None,
+                        },
+                        usage: None,
+                    });
+                    events.push(StreamEvent::MessageStop);
+                    events
+                }
+                event => vec![event],
+            };
+            for mut event in events {
+                if matches!(event, StreamEvent::MessageStop | StreamEvent::Error { .. }) {
+                    stopped = true;
+                }
+                counter.count(&mut event);
+                yield sse_event(event);
+            }
+        }
+        if !stopped {
+            yield sse_event(truncated_event());
+        }
+    }
+}
+
 impl<S> From<S> for Message
 where
     S: Into<String>,
@@ -60,6 +473,206 @@ where
 }
 
 impl ClaudeState {
+    /// Checks that the response of Claude Web has content before forwarding it
+    ///
+    /// Non-stream responses are read completely and must contain a complete message.
+    /// Streams are held back until their first content token, unless disabled in the
+    /// config. Whatever was read is replayed in front of the rest of the stream.
+    ///
+    /// # Arguments
+    /// * `input` - The response stream from the Claude Web API
+    ///
+    /// # Returns
+    /// * `Result<impl Stream, ClewdrError>` - The same stream of bytes, or an error
+    ///   if the response is empty, truncated or failed upstream
+    pub async fn check_response(
+        &self,
+        input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    ) -> Result<impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static, ClewdrError>
+    {
+        let complete = !self.stream;
+        if !complete && !CLEWDR_CONFIG.load().buffer_first_token {
+            return Ok(Either::Left(input));
+        }
+        let mut input = Box::pin(input);
+        let mut buf = vec![];
+        let mut check = ResponseCheck::default();
+        while let Some(chunk) = input.next().await {
+            let chunk = chunk.context(RquestSnafu {
+                msg: "Failed to read Claude response",
+            })?;
+            check.push(&chunk)?;
+            buf.push(chunk);
+            if check.content && !complete {
+                break;
+            }
+        }
+        if !check.content {
+            return Err(ClewdrError::EmptyChoices);
+        }
+        if complete && !check.stopped {
+            return Err(ClewdrError::TruncatedResponse);
+        }
+        Ok(Either::Right(
+            stream::iter(buf.into_iter().map(Ok)).chain(input),
+        ))
+    }
+
+    /// Wraps the response stream from Claude Web to record the usage of the cookie
+    ///
+    /// The bytes are passed through unchanged. When the stream ends, the generated text
+    /// is counted and the outcome is reported to the cookie manager. The reply is also
+    /// recorded in the kept conversation, if any.
+    ///
+    /// # Arguments
+    /// * `input` - The response stream from the Claude Web API
+    ///
+    /// # Returns
+    /// The same stream of bytes
+    pub fn track_usage(
+        &self,
+        input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    ) -> impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static {
+        let state = self.to_owned();
+        stream! {
+            let mut buf = Vec::new();
+            let mut success = true;
+            for await chunk in input {
+                match chunk {
+                    Ok(ref bytes) => buf.extend_from_slice(bytes),
+                    Err(_) => success = false,
+                }
+                yield chunk;
+            }
+            // a response cut off or failed upstream is not a success
+            let mut check = ResponseCheck::default();
+            let success = success && check.push(&buf).is_ok() && check.stopped;
+            let usage = CookieUsage {
+                success,
+                input_tokens: state.input_tokens,
+                output_tokens: count_tokens(&extract_completion(&buf)),
+            };
+            state.return_cookie(None, Some(usage)).await;
+            // the kept conversation can be continued once its reply is known
+            if let (Some(key), Some(conversation)) = (state.conversation_key, &state.conversation) {
+                if success {
+                    CONVERSATIONS.set_reply(key, &conversation.conv_uuid, &extract_reply(&buf));
+                } else {
+                    CONVERSATIONS.remove_chat(key, &conversation.conv_uuid);
+                }
+            }
+        }
+    }
+
+    /// Collects a Claude.ai event stream into a complete message
+    ///
+    /// Text, thinking and tool use blocks are assembled from their deltas, tool calls
+    /// are parsed out of the text if the request has tools, and the usage is estimated
+    /// from the prompt and the generated text.
+    ///
+    /// # Arguments
+    /// * `input` - The response stream from the Claude Web API
+    ///
+    /// # Returns
+    /// * `CreateMessageResponse` - The complete message
+    async fn collect_message(
+        &self,
+        input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    ) -> CreateMessageResponse {
+        let events = input
+            .eventsource()
+            .filter_map(async |e| e.ok())
+            .collect::<Vec<_>>()
+            .await;
+        let mut id = None;
+        let mut content = vec![];
+        // Claude.ai indices of the blocks mapped to their position in the content
+        let mut blocks = HashMap::new();
+        let mut inputs = HashMap::<usize, String>::new();
+        let mut delta = MessageDeltaContent::default();
+        for event in events {
+            let Ok(event) = serde_json::from_str::<StreamEvent>(&event.data) else {
+                continue;
+            };
+            match event {
+                StreamEvent::MessageStart { message } if !message.id.is_empty() => {
+                    id = Some(message.id);
+                }
+                StreamEvent::ContentBlockStart {
+                    index,
+                    content_block,
+                } => {
+                    blocks.insert(index, content.len());
+                    content.push(content_block);
+                }
+                StreamEvent::ContentBlockDelta { index, delta } => {
+                    let Some(block) = blocks.get(&index).and_then(|&i| content.get_mut(i)) else {
+                        continue;
+                    };
+                    match (block, delta) {
+                        (ContentBlock::Text { text }, ContentBlockDelta::TextDelta { text: t }) => {
+                            text.push_str(&t)
+                        }
+                        (
+                            ContentBlock::Thinking { thinking, .. },
+                            ContentBlockDelta::ThinkingDelta { thinking: t },
+                        ) => thinking.push_str(&t),
+                        (
+                            ContentBlock::Thinking { signature, .. },
+                            ContentBlockDelta::SignatureDelta { signature: s },
+                        ) => *signature = Some(s),
+                        (
+                            ContentBlock::ToolUse { .. },
+                            ContentBlockDelta::InputJsonDelta { partial_json },
+                        ) => inputs.entry(index).or_default().push_str(&partial_json),
+                        _ => {}
+                    }
+                }
+                StreamEvent::MessageDelta { delta: d, .. } => delta = d,
+                _ => {}
+            }
+        }
+        for (index, json) in inputs {
+            if let Some(ContentBlock::ToolUse { input, .. }) =
+                blocks.get(&index).and_then(|&i| content.get_mut(i))
+            {
+                *input = serde_json::from_str(&json).unwrap_or_else(|_| json!({}));
+            }
+        }
+        if self.tool_use {
+            content = content
+                .into_iter()
+                .flat_map(|block| match block {
+                    ContentBlock::Text { text } => parse_tool_calls(&text),
+                    block => vec![block],
+                })
+                .collect();
+        }
+        if self.tool_use
+            && content
+                .iter()
+                .any(|b| matches!(b, ContentBlock::ToolUse { .. }))
+        {
+            delta.stop_reason = Some(StopReason::ToolUse);
+            delta.stop_sequence = None;
+        }
+        let mut message = CreateMessageResponse {
+            content,
+            id: id.unwrap_or_else(|| format!("msg_{}", uuid::Uuid::new_v4().simple())),
+            model: self.model.to_owned(),
+            role: Role::Assistant,
+            stop_reason: Some(delta.stop_reason.unwrap_or(StopReason::EndTurn)),
+            stop_sequence: delta.stop_sequence,
+            type_: "message".to_string(),
+            usage: Usage {
+                input_tokens: self.input_tokens as u32,
+                output_tokens: 0,
+            },
+        };
+        message.usage.output_tokens = message.count_output_tokens();
+        message
+    }
+
     /// Converts the response from the Claude Web into Claude API or OpenAI API format
     ///
     /// This method transforms streams of bytes from Claude's web response into the appropriate
@@ -84,13 +697,21 @@ impl ClaudeState {
         // response is used for returning
         // not streaming
         if !self.stream {
-            let stream = input.eventsource();
-            let text = merge_sse(stream).await;
-            print_out_text(&text, "non_stream.txt");
-            return Json(Message::from(text)).into_response();
+            let message = self.collect_message(input).await;
+            print_out_json(&message, "non_stream.json");
+            return Json(message).into_response();
+        }
+
+        // extract tool calls from the stream
+        if self.tool_use {
+            return Sse::new(tool_use_stream(input.eventsource(), self.input_tokens))
+                .keep_alive(Default::default())
+                .into_response();
         }
 
-        // stream the response
-        Body::from_stream(input).into_response()
+        // stream the response with its usage
+        Sse::new(usage_stream(input.eventsource(), self.input_tokens))
+            .keep_alive(Default::default())
+            .into_response()
     }
 }
@@ -0,0 +1,398 @@
+use rand::{Rng, distr::Alphanumeric, rng};
+use serde_json::{Value, json};
+use std::fmt::Write;
+
+use crate::types::claude_message::{ContentBlock, Tool, ToolChoice, ToolResultContent};
+
+/// Tag opening a tool call written by the model
+const OPEN: &str = "<tool_call>";
+/// Tag closing a tool call written by the model
+const CLOSE: &str = "</tool_call>";
+
+/// Renders the tool definitions and the calling convention into the prompt
+///
+/// # Arguments
+/// * `tools` - Tools that the model may use
+/// * `choice` - How the model should use the tools
+///
+/// # Returns
+/// Instructions to put in front of the conversation
+pub fn render_tools(tools: &[Tool], choice: Option<&ToolChoice>) -> String {
+    let mut w = String::from(
+        "In this environment you have access to a set of tools you can use to answer the user's question.\n\
+         To call a tool, write a <tool_call> block containing a JSON object with the name of the tool and its input, for example:\n\
+         <tool_call>\n\
+         {\"name\": \"$TOOL_NAME\", \"input\": {\"$PARAMETER_NAME\": \"$PARAMETER_VALUE\"}}\n\
+         </tool_call>\n\
+         You may call several tools at once by writing several blocks. \
+         After your tool calls, end your response: the results will be provided in <tool_result> blocks in the next message. \
+         Never write a <tool_result> block yourself.\n\n\
+         Here are the tools available, with their input described as a JSON schema:\n<tools>\n",
+    );
+    for tool in tools {
+        let tool = json!({
+            "name": tool.name,
+            "description": tool.description,
+            "input_schema": tool.input_schema,
+        });
+        let _ = writeln!(w, "{}", tool);
+    }
+    w.push_str("</tools>");
+    match choice {
+        Some(ToolChoice::Any) => {
+            w.push_str("\n\nYou must call at least one tool in your response.")
+        }
+        Some(ToolChoice::Tool { name }) => {
+            let _ = write!(w, "\n\nYou must call the tool `{}` in your response.", name);
+        }
+        _ => {}
+    }
+    w
+}
+
+/// Renders a tool call of a previous assistant turn into the prompt
+///
+/// # Arguments
+/// * `name` - Name of the called tool
+/// * `input` - Input of the call
+///
+/// # Returns
+/// The call in the same format the model is asked to use
+pub fn render_tool_use(name: &str, input: &Value) -> String {
+    let call = json!({ "name": name, "input": input });
+    format!("{}\n{}\n{}", OPEN, call, CLOSE)
+}
+
+/// Renders the result of a tool call into the prompt
+///
+/// # Arguments
+/// * `id` - Identifier of the tool call
+/// * `content` - Result returned by the tool
+/// * `is_error` - Whether the tool failed
+///
+/// # Returns
+/// The result wrapped in a `<tool_result>` block
+pub fn render_tool_result(id: &str, content: &ToolResultContent, is_error: bool) -> String {
+    let content = match content {
+        ToolResultContent::Text(text) => text.trim().to_string(),
+        ToolResultContent::Blocks(blocks) => blocks
+            .iter()
+            .filter_map(|b| match b {
+                ContentBlock::Text { text } => Some(text.trim()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    let error = if is_error { " is_error=\"true\"" } else { "" };
+    format!(
+        "<tool_result tool_use_id=\"{}\"{}>\n{}\n</tool_result>",
+        id, error, content
+    )
+}
+
+/// Generates an identifier for a tool call parsed from the model output
+pub fn tool_use_id() -> String {
+    let suffix = rng()
+        .sample_iter(Alphanumeric)
+        .take(24)
+        .map(char::from)
+        .collect::<String>();
+    format!("toolu_{}", suffix)
+}
+
+/// Part of the model output, either text or a tool call
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Text(String),
+    ToolCall { name: String, input: Value },
+}
+
+/// Parses a tool call written by the model
+/// Accepts `input`, `arguments` or `parameters` for the input, also as a JSON string
+///
+/// # Arguments
+/// * `call` - Content of a `<tool_call>` block
+///
+/// # Returns
+/// * `Option<Segment>` - The tool call, or None if the content is not a valid call
+fn parse_call(call: &str) -> Option<Segment> {
+    let call = call
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    let value = serde_json::from_str::<Value>(call).ok()?;
+    let name = value["name"].as_str()?.to_string();
+    let input = ["input", "arguments", "parameters"]
+        .iter()
+        .map(|k| &value[*k])
+        .find(|v| !v.is_null())
+        .cloned()
+        .unwrap_or_else(|| json!({}));
+    let input = match input {
+        Value::String(s) => serde_json::from_str(&s).unwrap_or_else(|_| json!({})),
+        input => input,
+    };
+    Some(Segment::ToolCall { name, input })
+}
+
+/// Incremental parser extracting tool calls from the model output
+///
+/// Text is released as soon as it cannot be the start of a tool call.
+/// Once a tool call was made, anything but further tool calls is dropped,
+/// as the model is expected to stop and wait for the results.
+#[derive(Debug, Default)]
+pub struct ToolCallParser {
+    buf: String,
+    in_call: bool,
+    called: bool,
+    ended: bool,
+}
+
+impl ToolCallParser {
+    /// Whether the parser found at least one tool call
+    pub fn called(&self) -> bool {
+        self.called
+    }
+
+    /// Feeds a chunk of the model output to the parser
+    ///
+    /// # Arguments
+    /// * `text` - The next chunk of text
+    ///
+    /// # Returns
+    /// Segments that are complete after this chunk
+    pub fn push(&mut self, text: &str) -> Vec<Segment> {
+        let mut segments = vec![];
+        if self.ended {
+            return segments;
+        }
+        self.buf.push_str(text);
+        loop {
+            if self.in_call {
+                let Some(end) = self.buf.find(CLOSE) else {
+                    break;
+                };
+                let call = self.buf[..end].to_string();
+                self.buf.drain(..end + CLOSE.len());
+                self.in_call = false;
+                match parse_call(&call) {
+                    Some(segment) => {
+                        self.called = true;
+                        segments.push(segment);
+                    }
+                    None => self.text(format!("{}{}{}", OPEN, call, CLOSE), &mut segments),
+                }
+                continue;
+            }
+            if let Some(start) = self.buf.find(OPEN) {
+                let text = self.buf[..start].to_string();
+                self.buf.drain(..start + OPEN.len());
+                self.text(text, &mut segments);
+                if self.ended {
+                    break;
+                }
+                self.in_call = true;
+                continue;
+            }
+            // keep a possible beginning of the opening tag
+            let keep = (1..OPEN.len())
+                .rev()
+                .find(|&k| self.buf.ends_with(&OPEN[..k]))
+                .unwrap_or_default();
+            let text = self.buf[..self.buf.len() - keep].to_string();
+            self.buf.drain(..self.buf.len() - keep);
+            if self.called && text.trim().is_empty() {
+                // whitespace between tool calls
+                self.buf.insert_str(0, &text);
+                break;
+            }
+            self.text(text, &mut segments);
+            break;
+        }
+        if self.ended {
+            self.buf.clear();
+        }
+        segments
+    }
+
+    /// Flushes the rest of the output at the end of the response
+    /// A tool call cut off before its closing tag is still parsed if possible
+    ///
+    /// # Returns
+    /// The remaining segments
+    pub fn finish(&mut self) -> Vec<Segment> {
+        let mut segments = vec![];
+        if self.ended {
+            return segments;
+        }
+        let rest = std::mem::take(&mut self.buf);
+        if self.in_call {
+            self.in_call = false;
+            match parse_call(&rest) {
+                Some(segment) => {
+                    self.called = true;
+                    segments.push(segment);
+                }
+                None => self.text(format!("{}{}", OPEN, rest), &mut segments),
+            }
+        } else {
+            self.text(rest, &mut segments);
+        }
+        self.ended = true;
+        segments
+    }
+
+    /// Releases text, ending the output if it follows a tool call
+    fn text(&mut self, text: String, segments: &mut Vec<Segment>) {
+        if self.called {
+            if !text.trim().is_empty() {
+                self.ended = true;
+            }
+            return;
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+    }
+}
+
+/// Splits a complete model output into text and tool calls
+///
+/// # Arguments
+/// * `text` - The model output
+///
+/// # Returns
+/// Content blocks of the output, with generated tool call identifiers
+pub fn parse_tool_calls(text: &str) -> Vec<ContentBlock> {
+    let mut parser = ToolCallParser::default();
+    let mut segments = parser.push(text);
+    segments.extend(parser.finish());
+    let mut blocks = vec![];
+    let mut buf = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => buf.push_str(&text),
+            Segment::ToolCall { name, input } => {
+                if !buf.trim().is_empty() {
+                    blocks.push(ContentBlock::text(buf.trim()));
+                }
+                buf.clear();
+                blocks.push(ContentBlock::ToolUse {
+                    id: tool_use_id(),
+                    name,
+                    input,
+                });
+            }
+        }
+    }
+    if !buf.trim().is_empty() {
+        blocks.push(ContentBlock::text(buf.trim()));
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(chunks: &[&str]) -> (Vec<Segment>, bool) {
+        let mut parser = ToolCallParser::default();
+        let mut segments = chunks
+            .iter()
+            .flat_map(|c| parser.push(c))
+            .collect::<Vec<_>>();
+        segments.extend(parser.finish());
+        (segments, parser.called())
+    }
+
+    fn call(name: &str, input: Value) -> Segment {
+        Segment::ToolCall {
+            name: name.to_string(),
+            input,
+        }
+    }
+
+    #[test]
+    fn tag_split_across_chunks() {
+        let (segments, called) = feed(&[
+            "Let me check. <tool_",
+            "call>{\"name\": \"weather\", \"input\": {\"city\": \"Paris\"}}</tool_",
+            "call>",
+        ]);
+        assert!(called);
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Text("Let me check. ".to_string()),
+                call("weather", json!({ "city": "Paris" })),
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_tag_held_back() {
+        let mut parser = ToolCallParser::default();
+        assert_eq!(parser.push("a <"), vec![Segment::Text("a ".to_string())]);
+        assert_eq!(parser.push("b"), vec![Segment::Text("<b".to_string())]);
+        assert_eq!(parser.push("<tool"), vec![]);
+        assert_eq!(parser.finish(), vec![Segment::Text("<tool".to_string())]);
+        assert!(!parser.called());
+    }
+
+    #[test]
+    fn invalid_json_falls_back_to_text() {
+        let (segments, called) = feed(&["<tool_call>not json</tool_call> done"]);
+        assert!(!called);
+        let text = segments
+            .iter()
+            .map(|s| match s {
+                Segment::Text(text) => text.as_str(),
+                Segment::ToolCall { .. } => panic!("unexpected tool call"),
+            })
+            .collect::<String>();
+        assert_eq!(text, "<tool_call>not json</tool_call> done");
+    }
+
+    #[test]
+    fn text_after_call_is_dropped() {
+        let (segments, called) = feed(&[
+            "<tool_call>{\"name\": \"search\", \"arguments\": \"{\\\"q\\\": \\\"rust\\\"}\"}</tool_call>",
+            "\nThe results show",
+            " <tool_call>{\"name\": \"late\"}</tool_call>",
+        ]);
+        assert!(called);
+        assert_eq!(segments, vec![call("search", json!({ "q": "rust" }))]);
+    }
+
+    #[test]
+    fn multiple_calls() {
+        let (segments, _) = feed(&[
+            "<tool_call>\n{\"name\": \"a\", \"input\": {}}\n</tool_call>\n",
+            "<tool_call>```json\n{\"name\": \"b\", \"parameters\": {\"n\": 1}}\n```</tool_call>",
+        ]);
+        assert_eq!(
+            segments,
+            vec![call("a", json!({})), call("b", json!({ "n": 1 }))]
+        );
+
+        let blocks = parse_tool_calls(
+            "Two calls:\n<tool_call>{\"name\": \"a\"}</tool_call>\n<tool_call>{\"name\": \"b\"}</tool_call>",
+        );
+        assert_eq!(blocks.len(), 3);
+        assert!(matches!(&blocks[0], ContentBlock::Text { text } if text == "Two calls:"));
+        let names = blocks[1..]
+            .iter()
+            .map(|b| match b {
+                ContentBlock::ToolUse { id, name, .. } => {
+                    assert!(id.starts_with("toolu_"));
+                    name.as_str()
+                }
+                _ => panic!("expected a tool call"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+    }
+}
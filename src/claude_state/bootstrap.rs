@@ -6,7 +6,7 @@ use std::fmt::Write;
 
 use crate::{
     claude_state::ClaudeState,
-    config::{CLEWDR_CONFIG, Reason},
+    config::{AccountFlag, AccountInfo, CLEWDR_CONFIG, PlanTier, Reason},
     error::{CheckClaudeErr, ClewdrError, RquestSnafu},
     utils::print_out_json,
 };
@@ -70,6 +70,9 @@ impl ClaudeState {
                     .collect::<Vec<_>>()
             })
             .unwrap_or_default();
+        if let Some(cookie) = self.cookie.as_mut() {
+            cookie.capabilities = self.capabilities.to_owned();
+        }
         if !self.is_pro() && CLEWDR_CONFIG.load().skip_non_pro {
             return Err(Reason::NonPro.into());
         }
@@ -117,6 +120,20 @@ impl ClaudeState {
                 msg: "Failed to find a valid organization in response",
             })?;
 
+        if let Some(cookie) = self.cookie.as_mut() {
+            // recorded before checking the flags, so unusable accounts keep their information
+            cookie.account = Some(AccountInfo {
+                email: email.to_string(),
+                tier: Some(PlanTier::from_capabilities(&self.capabilities)),
+                org_uuid: acc_info
+                    .get("uuid")
+                    .and_then(|u| u.as_str())
+                    .map(|u| u.to_string()),
+                flags: parse_flags(acc_info),
+                updated_at: chrono::Utc::now().timestamp(),
+            });
+        }
+
         self.check_flags(acc_info, w)?;
 
         let u =
@@ -206,3 +223,28 @@ impl ClaudeState {
         Ok(())
     }
 }
+
+/// Parses all flags of an account, including expired ones
+///
+/// # Arguments
+/// * `acc_info` - Account information JSON containing active flags
+///
+/// # Returns
+/// * `Vec<AccountFlag>` - The flags with their expiry
+fn parse_flags(acc_info: &Value) -> Vec<AccountFlag> {
+    let Some(active_flags) = acc_info.get("active_flags").and_then(|a| a.as_array()) else {
+        return vec![];
+    };
+    active_flags
+        .iter()
+        .filter_map(|f| {
+            let r#type = f["type"].as_str()?;
+            let expire = f["expires_at"].as_str()?;
+            let expire = chrono::DateTime::parse_from_rfc3339(expire).ok()?;
+            Some(AccountFlag {
+                r#type: r#type.to_string(),
+                expires_at: expire.timestamp(),
+            })
+        })
+        .collect()
+}
@@ -1,16 +1,19 @@
+use axum::http::HeaderValue;
 use colored::Colorize;
-use futures::TryFutureExt;
 use rquest::{Method, Response, header::ACCEPT};
-use scopeguard::defer;
-use serde_json::json;
+use serde_json::{Value, json};
 use snafu::ResultExt;
 use tokio::spawn;
 use tracing::{Instrument, Level, debug, error, info, span, warn};
 
 use crate::{
-    config::CLEWDR_CONFIG,
+    claude_body::RequestBody,
+    config::{CLEWDR_CONFIG, CookieUsage, WARNING_HEADER},
     error::{CheckClaudeErr, ClewdrError, RquestSnafu},
-    services::cache::{CACHE, GetHashKey},
+    services::{
+        cache::{CACHE, GetHashKey},
+        conversation::{CONVERSATIONS, Conversation},
+    },
     types::claude_message::CreateMessageParams,
     utils::print_out_json,
 };
@@ -36,7 +39,14 @@ impl ClaudeState {
         let key = p.get_hash();
         if let Some(stream) = CACHE.pop(key).await {
             info!("[CACHE] found response for key: {}", key);
-            return Some(self.transform_response(stream).await);
+            let mut state = self.to_owned();
+            state.tool_use = p.uses_tools();
+            state.model = p.model.to_owned();
+            state.input_tokens = state
+                .transform_request(p.to_owned())
+                .map(|body| body.count_tokens())
+                .unwrap_or_default();
+            return Some(state.transform_response(stream).await);
         }
         for id in 0..CLEWDR_CONFIG.load().cache_response {
             let mut state = self.to_owned();
@@ -70,6 +80,14 @@ impl ClaudeState {
         &mut self,
         p: CreateMessageParams,
     ) -> Result<axum::response::Response, ClewdrError> {
+        // a conversation id sent by the client takes precedence
+        let header = self.fingerprint;
+        self.fingerprint = Some(header.unwrap_or_else(|| p.fingerprint()));
+        // the user id is shared by all the conversations of a user, so it is not a key
+        self.conversation_key = Some(header.unwrap_or_else(|| p.history_fingerprint()));
+        self.tool_use = p.uses_tools();
+        self.model = p.model.to_owned();
+        let mut err = None;
         for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
             if i > 0 {
                 info!("[RETRY] attempt: {}", i.to_string().green());
@@ -77,54 +95,87 @@ impl ClaudeState {
             let mut state = self.to_owned();
             let p = p.to_owned();
 
-            state.request_cookie().await?;
-
-            let defer_clone = state.to_owned();
-            defer! {
-                // ensure the cookie is returned
-                spawn(async move {
-                    defer_clone.return_cookie(None).await;
-                });
+            // the lease is released when the state and the response stream are dropped
+            if let Err(e) = state.request_cookie().await {
+                // report why the excluded cookies failed rather than the empty pool
+                return Err(err.unwrap_or(e));
             }
             // check if request is successful
-            let web_res = async { state.bootstrap().await.and(state.send_chat(p).await) };
-            let transform_res =
-                web_res.and_then(async |r| Ok(self.transform_response(r.bytes_stream()).await));
+            let web_res = state.bootstrap().await.and(state.send_chat(p).await);
 
-            match transform_res.await {
-                Ok(b) => {
-                    if let Err(e) = state.clean_chat().await {
+            match web_res {
+                Ok(r) => {
+                    // retry empty or failed responses before anything is forwarded
+                    let stream = match state.check_response(r.bytes_stream()).await {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            state.discard_chat().await;
+                            warn!(
+                                "[{}] {}",
+                                state.cookie.as_ref().unwrap().cookie.ellipse().green(),
+                                e
+                            );
+                            let usage = Some(CookieUsage::failure(state.input_tokens));
+                            state.return_cookie(None, usage).await;
+                            // retry on another cookie, which also takes over the conversation
+                            if let Some(ref cookie) = state.cookie {
+                                self.exclude.push(cookie.cookie.to_owned());
+                            }
+                            err = Some(e);
+                            continue;
+                        }
+                    };
+                    // usage is reported to the cookie manager when the stream ends
+                    let stream = state.track_usage(stream);
+                    self.input_tokens = state.input_tokens;
+                    let mut b = self.transform_response(stream).await;
+                    if state.thinking_downgraded {
+                        b.headers_mut().insert(
+                            WARNING_HEADER,
+                            HeaderValue::from_static("thinking disabled, the cookie is not Pro"),
+                        );
+                    }
+                    // kept conversations are continued by the next request
+                    let cleaned = match state.conversation {
+                        Some(_) => Ok(()),
+                        None => state.clean_chat().await,
+                    };
+                    if let Err(e) = cleaned {
                         warn!("Failed to clean chat: {}", e);
                     }
                     return Ok(b);
                 }
                 Err(e) => {
                     // delete chat after an error
-                    if let Err(e) = state.clean_chat().await {
-                        warn!("Failed to clean chat: {}", e);
-                    }
+                    state.discard_chat().await;
                     error!(
                         "[{}] {}",
                         state.cookie.as_ref().unwrap().cookie.ellipse().green(),
                         e
                     );
+                    let usage = Some(CookieUsage::failure(state.input_tokens));
                     // 429 error
                     if let ClewdrError::InvalidCookie { reason } = e {
-                        state.return_cookie(Some(reason.to_owned())).await;
+                        state.return_cookie(Some(reason.to_owned()), usage).await;
                         continue;
                     }
+                    state.return_cookie(None, usage).await;
                     return Err(e);
                 }
             }
         }
         error!("Max retries exceeded");
+        if let Some(e) = err {
+            return Err(e);
+        }
         Err(ClewdrError::TooManyRetries)
     }
 
     /// Sends a message to the Claude API by creating a new conversation and processing the request
     ///
     /// This method performs several key operations:
-    /// - Creates a new conversation with a unique UUID
+    /// - Continues the Claude.ai conversation kept for the client if enabled and possible
+    /// - Otherwise creates a new conversation with a unique UUID
     /// - Configures thinking mode if applicable
     /// - Transforms the client request to the Claude API format
     /// - Handles image uploads if present
@@ -147,51 +198,61 @@ impl ClaudeState {
                 msg: "Organization UUID is not set",
             })?;
 
-        // Create a new conversation
-        let new_uuid = uuid::Uuid::new_v4().to_string();
-        let endpoint = format!(
-            "{}/api/organizations/{}/chat_conversations",
-            self.endpoint, org_uuid
-        );
-        let body = json!({
-            "uuid": new_uuid,
-            "name": format!("ClewdR-{}", new_uuid),
-        });
-
-        self.build_request(Method::POST, endpoint)
-            .json(&body)
-            .send()
-            .await
-            .context(RquestSnafu {
-                msg: "Failed to create new conversation",
-            })?
-            .check_claude()
-            .await?;
-        self.conv_uuid = Some(new_uuid.to_string());
-        debug!("New conversation created: {}", new_uuid);
+        // continue the kept conversation if the history allows it
+        let key = self
+            .conversation_key
+            .filter(|_| CLEWDR_CONFIG.load().reuse_conversations)
+            // cached responses and tool calls always use a new conversation
+            .filter(|_| self.key.is_none() && !self.tool_use);
+        let continued = match key {
+            Some(key) => self.continue_chat(key, &org_uuid, &p).await,
+            None => None,
+        };
+        let mut body = match continued {
+            Some(body) => body,
+            None => self.new_chat(&org_uuid, &p).await?,
+        };
+        let conv_uuid = self.conv_uuid.to_owned().unwrap_or_default();
+        if let (Some(key), Some(cookie)) = (key, self.cookie.as_ref()) {
+            let conversation = Conversation::new(
+                cookie.cookie.to_owned(),
+                self.event_sender.to_owned(),
+                org_uuid.to_owned(),
+                conv_uuid.to_owned(),
+                &p,
+            );
+            // the store owns the chat from now on, the reply is recorded once
+            // the response is complete, and a failed request removes it
+            CONVERSATIONS.insert(key, conversation.to_owned());
+            self.conversation = Some(conversation);
+        }
 
-        let mut body = json!({});
-        // enable thinking mode
-        body["settings"]["paprika_mode"] = if p.thinking.is_some() && self.is_pro() {
+        // enable thinking mode, Claude.ai has no setting for the budget
+        let thinking = p.thinking_enabled() && self.is_pro();
+        if p.thinking_enabled() && !thinking {
+            warn!("Thinking mode is only available for Pro cookies, disabled");
+            self.thinking_downgraded = true;
+        }
+        if let Some(budget) = p.thinking.as_ref().map(|t| t.budget_tokens()) {
+            debug!("Thinking budget: {}, enabled: {}", budget, thinking);
+        }
+        let mut settings = json!({});
+        settings["settings"]["paprika_mode"] = if thinking {
             "extended".into()
         } else {
             json!(null)
         };
-
         let endpoint = format!(
             "{}/api/organizations/{}/chat_conversations/{}",
-            self.endpoint, org_uuid, new_uuid
+            self.endpoint, org_uuid, conv_uuid
         );
         let _ = self
             .build_request(Method::PUT, endpoint)
-            .json(&body)
+            .json(&settings)
             .send()
             .await;
-        // generate the request body
-        // check if the request is empty
-        let mut body = self.transform_request(p).ok_or(ClewdrError::BadRequest {
-            msg: "Request body is empty",
-        })?;
+
+        self.input_tokens = body.count_tokens();
 
         // check images
         let images = body.images.drain(..).collect::<Vec<_>>();
@@ -204,7 +265,7 @@ impl ClaudeState {
         print_out_json(&body, "clewdr_req.json");
         let endpoint = format!(
             "{}/api/organizations/{}/chat_conversations/{}/completion",
-            self.endpoint, org_uuid, new_uuid
+            self.endpoint, org_uuid, conv_uuid
         );
 
         self.build_request(Method::POST, endpoint)
@@ -218,4 +279,107 @@ impl ClaudeState {
             .check_claude()
             .await
     }
+
+    /// Creates a new conversation and transforms the whole request into its first message
+    ///
+    /// # Arguments
+    /// * `org_uuid` - Organization to create the conversation in
+    /// * `p` - The client request body
+    ///
+    /// # Returns
+    /// * `Result<RequestBody, ClewdrError>` - The request body to send to the conversation
+    async fn new_chat(
+        &mut self,
+        org_uuid: &str,
+        p: &CreateMessageParams,
+    ) -> Result<RequestBody, ClewdrError> {
+        let new_uuid = uuid::Uuid::new_v4().to_string();
+        let endpoint = format!(
+            "{}/api/organizations/{}/chat_conversations",
+            self.endpoint, org_uuid
+        );
+        let body = json!({
+            "uuid": new_uuid,
+            "name": format!("ClewdR-{}", new_uuid),
+        });
+
+        self.build_request(Method::POST, endpoint)
+            .json(&body)
+            .send()
+            .await
+            .context(RquestSnafu {
+                msg: "Failed to create new conversation",
+            })?
+            .check_claude()
+            .await?;
+        self.conv_uuid = Some(new_uuid.to_string());
+        debug!("New conversation created: {}", new_uuid);
+
+        // generate the request body
+        // check if the request is empty
+        self.transform_request(p.to_owned())
+            .ok_or(ClewdrError::BadRequest {
+                msg: "Request body is empty",
+            })
+    }
+
+    /// Prepares the new turn of the Claude.ai conversation kept for the client conversation
+    ///
+    /// The kept conversation is only continued on the cookie owning it, and if the
+    /// client history matches it. A diverged conversation is removed from the store,
+    /// which deletes it, so that a new one is built from the whole history. Otherwise
+    /// the new conversation replaces it once created.
+    ///
+    /// # Arguments
+    /// * `key` - Fingerprint of the client conversation
+    /// * `org_uuid` - Organization of the current cookie
+    /// * `p` - The client request body
+    ///
+    /// # Returns
+    /// * `Option<RequestBody>` - The request body with the new turn, None if the
+    ///   conversation cannot be continued
+    async fn continue_chat(
+        &mut self,
+        key: u64,
+        org_uuid: &str,
+        p: &CreateMessageParams,
+    ) -> Option<RequestBody> {
+        let kept = CONVERSATIONS.get(key)?;
+        if self.cookie.as_ref().map(|c| &c.cookie) != Some(&kept.cookie)
+            || kept.org_uuid != org_uuid
+        {
+            return None;
+        }
+        let Some(turn) = kept.new_turn(p) else {
+            info!("[CONV] history diverged, rebuilding conversation");
+            CONVERSATIONS.remove(key);
+            return None;
+        };
+        // the new turn replies to the last message of the conversation
+        let endpoint = format!(
+            "{}/api/organizations/{}/chat_conversations/{}?tree=False&rendering_mode=messages",
+            self.endpoint, org_uuid, kept.conv_uuid
+        );
+        let res = self
+            .build_request(Method::GET, endpoint)
+            .send()
+            .await
+            .ok()?
+            .check_claude()
+            .await
+            .inspect_err(|e| warn!("Failed to load kept conversation: {}", e))
+            .ok()?
+            .json::<Value>()
+            .await
+            .ok()?;
+        let parent = res["current_leaf_message_uuid"].as_str()?.to_string();
+        let body = self.transform_turn(p, turn, parent)?;
+        info!(
+            "[CONV] continuing conversation {} with {} new messages",
+            kept.conv_uuid,
+            turn.len().to_string().green()
+        );
+        self.conv_uuid = Some(kept.conv_uuid);
+        Some(body)
+    }
 }
@@ -6,15 +6,20 @@ use rquest::{
 use rquest_util::Emulation;
 use snafu::ResultExt;
 use strum::Display;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 use url::Url;
 
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 
 use crate::{
-    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, Reason},
+    config::{
+        CLAUDE_ENDPOINT, CLEWDR_CONFIG, ClewdrCookie, CookieStatus, CookieUsage, PlanTier, Reason,
+    },
     error::{ClewdrError, RquestSnafu},
-    services::cookie_manager::CookieEventSender,
+    services::{
+        conversation::{CONVERSATIONS, Conversation},
+        cookie_manager::{CookieEventSender, CookieLease, CookieRequest},
+    },
 };
 
 pub mod bootstrap;
@@ -39,6 +44,8 @@ pub enum ClaudeApiFormat {
 #[derive(Clone)]
 pub struct ClaudeState {
     pub cookie: Option<CookieStatus>,
+    /// Lease of the cookie, released once every clone of the state is dropped
+    pub lease: Option<Arc<CookieLease>>,
     cookie_header_value: HeaderValue,
     pub event_sender: CookieEventSender,
     pub org_uuid: Option<String>,
@@ -50,6 +57,23 @@ pub struct ClaudeState {
     pub stream: bool,
     pub client: Client,
     pub key: Option<(u64, usize)>,
+    pub input_tokens: u64,
+    /// Fingerprint of the conversation, used to keep it on the same cookie
+    pub fingerprint: Option<u64>,
+    /// Key of the Claude.ai conversation kept for the client conversation
+    pub conversation_key: Option<u64>,
+    /// Cookie groups the caller is restricted to
+    pub groups: Option<Vec<String>>,
+    /// Cookies that failed the request, skipped when retrying
+    pub exclude: Vec<ClewdrCookie>,
+    /// Whether tool calls are parsed out of the response
+    pub tool_use: bool,
+    /// Model requested by the client
+    pub model: String,
+    /// Claude.ai conversation kept for the client conversation
+    pub conversation: Option<Conversation>,
+    /// Whether the requested thinking mode was disabled because the cookie is not Pro
+    pub thinking_downgraded: bool,
 }
 
 impl ClaudeState {
@@ -58,6 +82,7 @@ impl ClaudeState {
         ClaudeState {
             event_sender,
             cookie: None,
+            lease: None,
             org_uuid: None,
             conv_uuid: None,
             cookie_header_value: HeaderValue::from_static(""),
@@ -68,6 +93,15 @@ impl ClaudeState {
             stream: false,
             client: SUPER_CLIENT.to_owned(),
             key: None,
+            input_tokens: 0,
+            fingerprint: None,
+            conversation_key: None,
+            groups: None,
+            exclude: Vec::new(),
+            tool_use: false,
+            model: String::new(),
+            conversation: None,
+            thinking_downgraded: false,
         }
     }
 
@@ -100,18 +134,28 @@ impl ClaudeState {
     /// Checks if the current user has pro capabilities
     /// Returns true if any capability contains "pro", "enterprise", "raven", or "max"
     pub fn is_pro(&self) -> bool {
-        self.capabilities.iter().any(|c| {
-            c.contains("pro")
-                || c.contains("enterprise")
-                || c.contains("raven")
-                || c.contains("max")
-        })
+        PlanTier::from_capabilities(&self.capabilities) >= PlanTier::Pro
     }
 
     /// Requests a new cookie from the cookie manager
     /// Updates the internal state with the new cookie and proxy configuration
     pub async fn request_cookie(&mut self) -> Result<(), ClewdrError> {
-        let res = self.event_sender.request().await?;
+        let lease = self
+            .event_sender
+            .request(CookieRequest {
+                fingerprint: self.fingerprint,
+                groups: self.groups.to_owned(),
+                exclude: self.exclude.to_owned(),
+            })
+            .await?;
+        let res = lease.cookie.to_owned();
+        self.lease = Some(Arc::new(lease));
+        self.set_cookie(res)
+    }
+
+    /// Uses the given cookie without leasing it from the cookie manager
+    /// Updates the internal state with the cookie and proxy configuration
+    pub fn set_cookie(&mut self, res: CookieStatus) -> Result<(), ClewdrError> {
         self.cookie = Some(res.to_owned());
         let mut client = ClientBuilder::new()
             .cookie_store(true)
@@ -131,11 +175,12 @@ impl ClaudeState {
 
     /// Returns the current cookie to the cookie manager
     /// Optionally provides a reason for returning the cookie (e.g., invalid, banned)
-    pub async fn return_cookie(&self, reason: Option<Reason>) {
+    /// and the outcome of the request to be recorded in the cookie statistics
+    pub async fn return_cookie(&self, reason: Option<Reason>, usage: Option<CookieUsage>) {
         // return the cookie to the cookie manager
         if let Some(ref cookie) = self.cookie {
             self.event_sender
-                .return_cookie(cookie.to_owned(), reason)
+                .return_cookie(cookie.to_owned(), reason, usage)
                 .await
                 .unwrap_or_else(|e| {
                     error!("Failed to send cookie: {}", e);
@@ -155,6 +200,33 @@ impl ClaudeState {
         let Some(ref conv_uuid) = self.conv_uuid else {
             return Ok(());
         };
+        self.delete_chat(org_uuid, conv_uuid).await;
+        Ok(())
+    }
+
+    /// Deletes the chat of a failed request
+    ///
+    /// A chat kept in the conversation store is removed from it, which deletes it,
+    /// so that the next request does not try to continue it.
+    pub async fn discard_chat(&self) {
+        let kept = self
+            .conversation_key
+            .zip(self.conv_uuid.as_deref())
+            .is_some_and(|(key, conv_uuid)| CONVERSATIONS.remove_chat(key, conv_uuid));
+        if kept {
+            return;
+        }
+        if let Err(e) = self.clean_chat().await {
+            warn!("Failed to clean chat: {}", e);
+        }
+    }
+
+    /// Deletes a chat conversation, ignoring failures
+    ///
+    /// # Arguments
+    /// * `org_uuid` - Organization of the conversation
+    /// * `conv_uuid` - UUID of the conversation
+    pub async fn delete_chat(&self, org_uuid: &str, conv_uuid: &str) {
         let endpoint = format!(
             "{}/api/organizations/{}/chat_conversations/{}",
             self.endpoint, org_uuid, conv_uuid
@@ -167,6 +239,5 @@ impl ClaudeState {
             .context(RquestSnafu {
                 msg: "Failed to delete chat conversation",
             });
-        Ok(())
     }
 }
@@ -9,7 +9,7 @@ use passwords::PasswordGenerator;
 use rquest::{Proxy, Url};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     env,
     fmt::{Debug, Display},
     net::{IpAddr, SocketAddr},
@@ -22,8 +22,12 @@ use yup_oauth2::ServiceAccountKey;
 
 use crate::{
     config::{
-        CookieStatus, UselessCookie, default_check_update, default_ip, default_max_retries,
-        default_padtxt_len, default_port, default_skip_cool_down, default_use_real_roles,
+        CookieStatus, DispatchStrategy, UselessCookie, VertexCredential, default_affinity_messages,
+        default_affinity_ttl, default_buffer_first_token, default_check_update,
+        default_cookie_wait_timeout, default_health_check_concurrency, default_ip,
+        default_key_403_threshold, default_key_cooldown, default_key_invalid_threshold,
+        default_max_retries, default_padtxt_len, default_port, default_skip_cool_down,
+        default_use_real_roles, default_webhook_interval, default_webhook_retries,
     },
     error::ClewdrError,
     utils::enabled,
@@ -54,13 +58,112 @@ fn generate_password() -> String {
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct VertexConfig {
+    /// Credential added to the Vertex pool, removed from the config once added
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub credential: Option<ServiceAccountKey>,
     pub model_id: Option<String>,
+    /// Default location of the Vertex endpoint, "global" if not set
+    #[serde(default)]
+    pub location: Option<String>,
+    /// Locations of models only available in specific regions
+    #[serde(default)]
+    pub model_locations: HashMap<String, String>,
+    /// Base URL replacing the Vertex host, e.g. a local mock
+    #[serde(default)]
+    pub base_url: Option<Url>,
 }
 
 impl VertexConfig {
-    pub fn validate(&self) -> bool {
-        self.credential.is_some()
+    /// Resolves the location of a request
+    /// A model location takes precedence over the credential location,
+    /// which takes precedence over the configured default
+    ///
+    /// # Arguments
+    /// * `model` - The model of the request
+    /// * `credential` - The credential used for the request
+    ///
+    /// # Returns
+    /// The location, "global" if none is configured
+    pub fn location_for(&self, model: &str, credential: &VertexCredential) -> String {
+        let model = model.trim_start_matches("google/");
+        self.model_locations
+            .get(model)
+            .or(credential.location.as_ref())
+            .or(self.location.as_ref())
+            .map(|l| l.trim().to_owned())
+            .filter(|l| !l.is_empty())
+            .unwrap_or_else(|| "global".to_string())
+    }
+
+    /// Gets the base URL of the Vertex API for a location
+    /// The global location uses `aiplatform.googleapis.com`, regions use
+    /// `{region}-aiplatform.googleapis.com`
+    ///
+    /// # Arguments
+    /// * `location` - The resolved location
+    ///
+    /// # Returns
+    /// The base URL without a trailing slash
+    pub fn endpoint(&self, location: &str) -> String {
+        if let Some(base_url) = self.base_url.as_ref() {
+            return base_url.as_str().trim_end_matches('/').to_string();
+        }
+        if location == "global" {
+            "https://aiplatform.googleapis.com".to_string()
+        } else {
+            format!("https://{}-aiplatform.googleapis.com", location)
+        }
+    }
+}
+
+/// Format of the payload sent to a webhook
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    /// Generic JSON payload with the event data
+    #[default]
+    Json,
+    /// Discord webhook message
+    Discord,
+    /// Telegram bot `sendMessage` request, requires `chat_id`
+    Telegram,
+}
+
+/// A webhook endpoint notified of pool events
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookTarget {
+    pub url: String,
+    #[serde(default)]
+    pub format: WebhookFormat,
+    #[serde(default)]
+    pub chat_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    #[serde(default)]
+    pub targets: Vec<WebhookTarget>,
+    /// Notify when the number of valid cookies drops below this value, 0 to disable
+    #[serde(default)]
+    pub pool_threshold: usize,
+    #[serde(default = "default_key_403_threshold")]
+    pub key_403_threshold: u32,
+    /// Minimum interval between two webhooks of the same kind in seconds
+    #[serde(default = "default_webhook_interval")]
+    pub min_interval: u64,
+    #[serde(default = "default_webhook_retries")]
+    pub max_retries: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            targets: vec![],
+            pool_threshold: 0,
+            key_403_threshold: default_key_403_threshold(),
+            min_interval: default_webhook_interval(),
+            max_retries: default_webhook_retries(),
+        }
     }
 }
 
@@ -71,10 +174,13 @@ pub struct ClewdrConfig {
     #[serde(default)]
     pub vertex: VertexConfig,
     #[serde(default)]
+    pub webhook: WebhookConfig,
+    // Pool entries are kept in the state file, these are only read for migration
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
     pub cookie_array: HashSet<CookieStatus>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
     pub wasted_cookie: HashSet<UselessCookie>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
     pub gemini_keys: HashSet<KeyStatus>,
 
     // Server settings, cannot hot reload
@@ -94,6 +200,9 @@ pub struct ClewdrConfig {
     password: String,
     #[serde(default)]
     admin_password: String,
+    /// Additional user API keys, mapped to the cookie groups they may use, no groups for every cookie
+    #[serde(default)]
+    api_keys: HashMap<String, Vec<String>>,
     #[serde(default)]
     pub proxy: Option<String>,
     #[serde(default)]
@@ -102,8 +211,14 @@ pub struct ClewdrConfig {
     // Api settings, can hot reload
     #[serde(default = "default_max_retries")]
     pub max_retries: usize,
+    /// Hold streams back until their first content token, so empty responses can be retried
+    #[serde(default = "default_buffer_first_token")]
+    pub buffer_first_token: bool,
     #[serde(default)]
     pub preserve_chats: bool,
+    /// Continue the Claude.ai conversation of a client conversation instead of rebuilding it
+    #[serde(default)]
+    pub reuse_conversations: bool,
     #[serde(default)]
     pub web_search: bool,
 
@@ -128,6 +243,31 @@ pub struct ClewdrConfig {
     pub skip_rate_limit: bool,
     #[serde(default)]
     pub skip_normal_pro: bool,
+    #[serde(default)]
+    pub dispatch_strategy: DispatchStrategy,
+    #[serde(default)]
+    pub max_concurrency_per_cookie: usize,
+    #[serde(default = "default_cookie_wait_timeout")]
+    pub cookie_wait_timeout: u64,
+    #[serde(default = "default_affinity_ttl")]
+    pub affinity_ttl: u64,
+    #[serde(default = "default_affinity_messages")]
+    pub affinity_messages: usize,
+    #[serde(default)]
+    pub health_check_interval: u64,
+    #[serde(default = "default_health_check_concurrency")]
+    pub health_check_concurrency: usize,
+    #[serde(default)]
+    pub revalidate_interval: u64,
+
+    // Key settings, can hot reload
+    #[serde(default = "default_key_invalid_threshold")]
+    pub key_invalid_threshold: u32,
+    #[serde(default = "default_key_cooldown")]
+    pub key_cooldown: u64,
+    /// Only accept submitted keys and cookies that pass a probe against the upstream
+    #[serde(default)]
+    pub probe_on_submit: bool,
 
     // Prompt configurations, can hot reload
     #[serde(default = "default_use_real_roles")]
@@ -154,6 +294,7 @@ impl Default for ClewdrConfig {
     fn default() -> Self {
         Self {
             vertex: Default::default(),
+            webhook: Default::default(),
             max_retries: default_max_retries(),
             check_update: default_check_update(),
             auto_update: false,
@@ -162,6 +303,7 @@ impl Default for ClewdrConfig {
             gemini_keys: HashSet::new(),
             password: String::new(),
             admin_password: String::new(),
+            api_keys: HashMap::new(),
             proxy: None,
             ip: default_ip(),
             port: default_port(),
@@ -175,6 +317,8 @@ impl Default for ClewdrConfig {
             rquest_proxy: None,
             pad_tokens: Arc::new(vec![]),
             preserve_chats: false,
+            reuse_conversations: false,
+            buffer_first_token: default_buffer_first_token(),
             web_search: false,
             cache_response: 0,
             not_hash_system: false,
@@ -185,6 +329,17 @@ impl Default for ClewdrConfig {
             skip_non_pro: false,
             skip_rate_limit: default_skip_cool_down(),
             skip_normal_pro: false,
+            dispatch_strategy: DispatchStrategy::default(),
+            max_concurrency_per_cookie: 0,
+            cookie_wait_timeout: default_cookie_wait_timeout(),
+            affinity_ttl: default_affinity_ttl(),
+            affinity_messages: default_affinity_messages(),
+            health_check_interval: 0,
+            health_check_concurrency: default_health_check_concurrency(),
+            revalidate_interval: 0,
+            key_invalid_threshold: default_key_invalid_threshold(),
+            key_cooldown: default_key_cooldown(),
+            probe_on_submit: false,
         }
     }
 }
@@ -223,6 +378,13 @@ impl Display for ClewdrConfig {
             web_url.to_string().green().underline(),
             self.admin_password.yellow(),
         )?;
+        if !self.api_keys.is_empty() {
+            writeln!(
+                f,
+                "Group API keys: {}",
+                self.api_keys.len().to_string().yellow()
+            )?;
+        }
         writeln!(
             f,
             "Response Caching: {}",
@@ -241,9 +403,6 @@ impl Display for ClewdrConfig {
                 self.pad_tokens.len().to_string().blue()
             )?
         }
-        if self.vertex.validate() {
-            writeln!(f, "Vertex {}", "Enabled".green().bold())?;
-        }
         writeln!(f, "Skip non Pro: {}", enabled(self.skip_non_pro))?;
         writeln!(f, "Skip restricted: {}", enabled(self.skip_restricted))?;
         writeln!(
@@ -258,13 +417,86 @@ impl Display for ClewdrConfig {
         )?;
         writeln!(f, "Skip normal Pro: {}", enabled(self.skip_normal_pro))?;
         writeln!(f, "Skip rate limit: {}", enabled(self.skip_rate_limit))?;
+        writeln!(f, "Probe on submit: {}", enabled(self.probe_on_submit))?;
+        writeln!(
+            f,
+            "Buffer first token: {}",
+            enabled(self.buffer_first_token)
+        )?;
+        writeln!(
+            f,
+            "Reuse conversations: {}",
+            enabled(self.reuse_conversations)
+        )?;
+        writeln!(
+            f,
+            "Dispatch strategy: {}",
+            self.dispatch_strategy.to_string().blue()
+        )?;
+        if !self.webhook.targets.is_empty() {
+            writeln!(
+                f,
+                "Webhooks: {}",
+                self.webhook.targets.len().to_string().blue()
+            )?;
+        }
+        if self.health_check_interval > 0 {
+            writeln!(
+                f,
+                "Cookie health check: every {}s",
+                self.health_check_interval.to_string().blue()
+            )?;
+        }
+        if self.revalidate_interval > 0 {
+            writeln!(
+                f,
+                "Invalid cookie revalidation: every {}s",
+                self.revalidate_interval.to_string().blue()
+            )?;
+        }
+        if self.affinity_ttl > 0 {
+            writeln!(
+                f,
+                "Conversation affinity: {}s",
+                self.affinity_ttl.to_string().blue()
+            )?;
+        }
+        if self.max_concurrency_per_cookie > 0 {
+            writeln!(
+                f,
+                "Max concurrency per cookie: {}",
+                self.max_concurrency_per_cookie.to_string().blue()
+            )?;
+            if self.cookie_wait_timeout > 0 {
+                writeln!(
+                    f,
+                    "Cookie wait timeout: {}s",
+                    self.cookie_wait_timeout.to_string().blue()
+                )?;
+            }
+        }
         Ok(())
     }
 }
 
 impl ClewdrConfig {
     pub fn user_auth(&self, key: &str) -> bool {
-        key == self.password
+        key == self.password || self.api_keys.contains_key(key)
+    }
+
+    /// Gets the cookie groups a user API key is restricted to
+    ///
+    /// # Arguments
+    /// * `key` - The user API key
+    ///
+    /// # Returns
+    /// * `Option<Vec<String>>` - The groups of the key, None if the key may use every cookie
+    pub fn key_groups(&self, key: &str) -> Option<Vec<String>> {
+        if key == self.password {
+            return None;
+        }
+        // a key listed without groups is not restricted
+        self.api_keys.get(key).filter(|g| !g.is_empty()).cloned()
     }
 
     pub fn admin_auth(&self, key: &str) -> bool {
@@ -11,6 +11,7 @@ use url::Url;
 use crate::{config::ClewdrConfig, utils::set_clewdr_dir};
 
 pub const CONFIG_NAME: &str = "clewdr.toml";
+pub const STATE_NAME: &str = "clewdr_state.json";
 pub const CLAUDE_ENDPOINT: &str = "https://api.anthropic.com";
 pub const GEMINI_ENDPOINT: &str = "https://generativelanguage.googleapis.com";
 pub static ENDPOINT_URL: LazyLock<Url> = LazyLock::new(|| {
@@ -19,6 +20,8 @@ pub static ENDPOINT_URL: LazyLock<Url> = LazyLock::new(|| {
     })
 });
 pub const LOG_DIR: &str = "log";
+/// Response header warning the client about a degraded request
+pub const WARNING_HEADER: &str = "x-clewdr-warning";
 pub static CLEWDR_CONFIG: LazyLock<ArcSwap<ClewdrConfig>> = LazyLock::new(|| {
     let _ = *CLEWDR_DIR;
     let config = ClewdrConfig::new();
@@ -57,6 +60,9 @@ pub static CONFIG_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
     }
 });
 
+/// Path of the pool state file, next to the config file
+pub static STATE_PATH: LazyLock<PathBuf> = LazyLock::new(|| CONFIG_PATH.with_file_name(STATE_NAME));
+
 pub static CLEWDR_DIR: LazyLock<PathBuf> =
     LazyLock::new(|| set_clewdr_dir().expect("Failed to get dir"));
 
@@ -116,5 +122,93 @@ pub const fn default_skip_cool_down() -> bool {
     true
 }
 
+/// Default setting for buffering streams until their first content token
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_buffer_first_token() -> bool {
+    true
+}
+
+/// Default time a request waits for a cookie below its concurrency limit in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 60 seconds
+pub const fn default_cookie_wait_timeout() -> u64 {
+    60
+}
+
+/// Default time to live of a conversation affinity in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 1800 seconds
+pub const fn default_affinity_ttl() -> u64 {
+    1800
+}
+
+/// Default number of messages of the first user turn used to fingerprint a conversation
+///
+/// # Returns
+/// * `usize` - The default value of 2
+pub const fn default_affinity_messages() -> usize {
+    2
+}
+
+/// Default number of cookies checked at the same time by the health checker
+///
+/// # Returns
+/// * `usize` - The default value of 2
+pub const fn default_health_check_concurrency() -> usize {
+    2
+}
+
+/// Default minimum interval between two webhooks of the same kind in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 60 seconds
+pub const fn default_webhook_interval() -> u64 {
+    60
+}
+
+/// Default number of times a failed webhook is retried
+///
+/// # Returns
+/// * `usize` - The default value of 3
+pub const fn default_webhook_retries() -> usize {
+    3
+}
+
+/// Default number of 403 responses after which a key triggers a webhook
+///
+/// # Returns
+/// * `u32` - The default value of 3
+pub const fn default_key_403_threshold() -> u32 {
+    3
+}
+
+/// Default number of 403 or 400 API_KEY_INVALID responses after which a key is invalidated
+///
+/// # Returns
+/// * `u32` - The default value of 5
+pub const fn default_key_invalid_threshold() -> u32 {
+    5
+}
+
+/// Default cooldown of a rate limited key in seconds, used when the response has no retry delay
+///
+/// # Returns
+/// * `u64` - The default value of 60
+pub const fn default_key_cooldown() -> u64 {
+    60
+}
+
+/// Default weight of a cookie for weighted dispatching
+///
+/// # Returns
+/// * `u32` - The default value of 1
+pub const fn default_weight() -> u32 {
+    1
+}
+
 /// Default cookie value for testing purposes
 pub const PLACEHOLDER_COOKIE: &str = "sk-ant-REDACTED";
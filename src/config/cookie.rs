@@ -8,7 +8,7 @@ use std::{
 };
 use tracing::{info, warn};
 
-use crate::config::PLACEHOLDER_COOKIE;
+use crate::config::{PLACEHOLDER_COOKIE, Reason, default_weight};
 
 /// A struct representing a cookie
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -19,11 +19,199 @@ pub struct ClewdrCookie {
 }
 
 /// A struct representing a cookie with its information
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CookieStatus {
     pub cookie: ClewdrCookie,
     #[serde(default)]
     pub reset_time: Option<i64>,
+    #[serde(default)]
+    pub stats: CookieStats,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub account: Option<AccountInfo>,
+}
+
+impl Default for CookieStatus {
+    fn default() -> Self {
+        Self {
+            cookie: ClewdrCookie::default(),
+            reset_time: None,
+            stats: CookieStats::default(),
+            weight: default_weight(),
+            capabilities: Vec::new(),
+            groups: Vec::new(),
+            account: None,
+        }
+    }
+}
+
+/// Plan tier of an account, derived from its capabilities
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanTier {
+    Free,
+    Pro,
+    Enterprise,
+    Max,
+}
+
+impl PlanTier {
+    /// Derives the plan tier from the capabilities of an organization
+    ///
+    /// # Arguments
+    /// * `capabilities` - Capabilities reported by Claude.ai
+    ///
+    /// # Returns
+    /// The best plan tier found in the capabilities
+    pub fn from_capabilities(capabilities: &[String]) -> Self {
+        let has = |s: &str| capabilities.iter().any(|c| c.contains(s));
+        if has("max") {
+            PlanTier::Max
+        } else if has("enterprise") || has("raven") {
+            PlanTier::Enterprise
+        } else if has("pro") {
+            PlanTier::Pro
+        } else {
+            PlanTier::Free
+        }
+    }
+}
+
+/// A warning, restriction or ban flag of an account
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AccountFlag {
+    /// Type of the flag, e.g. "first_warning" or "consumer_restricted_mode"
+    pub r#type: String,
+    /// Timestamp at which the flag expires
+    pub expires_at: i64,
+}
+
+impl AccountFlag {
+    /// Checks if the flag has not expired yet
+    pub fn is_active(&self) -> bool {
+        self.expires_at > chrono::Utc::now().timestamp()
+    }
+}
+
+/// Account information collected while bootstrapping a cookie
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AccountInfo {
+    /// Email address of the account
+    #[serde(default)]
+    pub email: String,
+    /// Plan tier of the organization used for chatting
+    #[serde(default)]
+    pub tier: Option<PlanTier>,
+    /// UUID of the organization used for chatting
+    #[serde(default)]
+    pub org_uuid: Option<String>,
+    /// Current and past flags of the account
+    #[serde(default)]
+    pub flags: Vec<AccountFlag>,
+    /// Timestamp of the last bootstrap
+    #[serde(default)]
+    pub updated_at: i64,
+}
+
+impl AccountInfo {
+    /// Merges newer account information into this one
+    /// Flags are accumulated, so expired flags are kept as history
+    ///
+    /// # Arguments
+    /// * `newer` - Account information from a later bootstrap
+    pub fn merge(&mut self, newer: AccountInfo) {
+        for flag in newer.flags {
+            if !self.flags.contains(&flag) {
+                self.flags.push(flag);
+            }
+        }
+        self.flags.sort_by_key(|f| f.expires_at);
+        if !newer.email.is_empty() {
+            self.email = newer.email;
+        }
+        self.tier = newer.tier.or(self.tier);
+        self.org_uuid = newer.org_uuid.or(self.org_uuid.take());
+        self.updated_at = newer.updated_at.max(self.updated_at);
+    }
+}
+
+/// Usage statistics accumulated for a cookie
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CookieStats {
+    /// Number of times the cookie has been dispatched
+    #[serde(default)]
+    pub requests: u64,
+    /// Number of requests that completed successfully
+    #[serde(default)]
+    pub successes: u64,
+    /// Number of requests that failed
+    #[serde(default)]
+    pub failures: u64,
+    /// Timestamp of the last dispatch
+    #[serde(default)]
+    pub last_used: Option<i64>,
+    /// Last reason the cookie was returned with
+    #[serde(default)]
+    pub last_error: Option<Reason>,
+    /// Approximate number of input tokens sent with the cookie
+    #[serde(default)]
+    pub input_tokens: u64,
+    /// Approximate number of output tokens received with the cookie
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+/// Outcome of a single request served with a cookie
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CookieUsage {
+    pub success: bool,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl CookieUsage {
+    /// Usage of a request that failed before producing any output
+    pub fn failure(input_tokens: u64) -> Self {
+        Self {
+            success: false,
+            input_tokens,
+            output_tokens: 0,
+        }
+    }
+}
+
+impl CookieStats {
+    /// Records that the cookie has been handed out for a request
+    pub fn record_dispatch(&mut self) {
+        self.requests += 1;
+        self.last_used = Some(chrono::Utc::now().timestamp());
+    }
+
+    /// Records the outcome of a request returned to the cookie manager
+    ///
+    /// # Arguments
+    /// * `reason` - Reason the cookie was returned with, if any
+    /// * `usage` - Outcome and token usage of the request, if any
+    pub fn record_return(&mut self, reason: Option<&Reason>, usage: Option<CookieUsage>) {
+        if let Some(reason) = reason {
+            self.last_error = Some(reason.to_owned());
+        }
+        let Some(usage) = usage else {
+            return;
+        };
+        if usage.success {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+        self.input_tokens += usage.input_tokens;
+        self.output_tokens += usage.output_tokens;
+    }
 }
 
 impl PartialEq for CookieStatus {
@@ -65,7 +253,44 @@ impl CookieStatus {
         Self {
             cookie: ClewdrCookie::from(cookie),
             reset_time,
+            ..Default::default()
+        }
+    }
+
+    /// Keeps only the fields an admin may set on a submitted cookie
+    /// Reset time, statistics, capabilities and account information are recorded by ClewdR
+    ///
+    /// # Returns
+    /// A new CookieStatus with the cookie, weight and groups of this one
+    pub fn submitted(self) -> Self {
+        Self {
+            cookie: self.cookie,
+            weight: self.weight,
+            groups: self.groups,
+            ..Default::default()
+        }
+    }
+
+    /// Checks if the cookie belongs to any of the given groups
+    ///
+    /// # Arguments
+    /// * `groups` - The groups to check, None matches every cookie
+    ///
+    /// # Returns
+    /// True if the cookie may be used by a caller restricted to the groups
+    pub fn in_groups(&self, groups: Option<&[String]>) -> bool {
+        groups.is_none_or(|groups| self.groups.iter().any(|g| groups.contains(g)))
+    }
+
+    /// Gets the plan tier of the cookie
+    ///
+    /// # Returns
+    /// The plan tier, or None if the cookie has not been bootstrapped yet
+    pub fn tier(&self) -> Option<PlanTier> {
+        if self.capabilities.is_empty() {
+            return None;
         }
+        Some(PlanTier::from_capabilities(&self.capabilities))
     }
 
     /// Checks if the cookie's reset time has expired
@@ -74,14 +299,15 @@ impl CookieStatus {
     /// # Returns
     /// The same CookieStatus with potentially updated reset_time
     pub fn reset(self) -> Self {
-        if let Some(t) = self.reset_time {
-            if t < chrono::Utc::now().timestamp() {
-                info!("Cookie reset time expired");
-                return Self {
-                    reset_time: None,
-                    ..self
-                };
-            }
+        if self
+            .reset_time
+            .is_some_and(|t| t < chrono::Utc::now().timestamp())
+        {
+            info!("Cookie reset time expired");
+            return Self {
+                reset_time: None,
+                ..self
+            };
         }
         self
     }
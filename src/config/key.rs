@@ -68,8 +68,119 @@ impl From<GeminiKey> for String {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct KeyStatus {
     pub key: GeminiKey,
+    /// Number of 403 or 400 API_KEY_INVALID responses received with the key
     #[serde(default)]
     pub count_403: u32,
+    /// Timestamp at which a rate limited key can be used again
+    #[serde(default)]
+    pub reset_time: Option<i64>,
+    /// Requests and tokens of the current day
+    #[serde(default)]
+    pub stats: KeyStats,
+}
+
+/// Daily usage statistics of a key
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct KeyStats {
+    /// UTC date the counters belong to, formatted as YYYY-MM-DD
+    #[serde(default)]
+    pub date: String,
+    /// Number of times the key has been dispatched
+    #[serde(default)]
+    pub requests: u64,
+    /// Number of prompt tokens reported by Gemini
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    /// Number of candidate tokens reported by Gemini
+    #[serde(default)]
+    pub candidate_tokens: u64,
+    /// Number of thought tokens reported by Gemini
+    #[serde(default)]
+    pub thought_tokens: u64,
+}
+
+/// Token usage of a single response served with a key
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyUsage {
+    pub prompt_tokens: u64,
+    pub candidate_tokens: u64,
+    pub thought_tokens: u64,
+}
+
+impl KeyStats {
+    /// Resets the counters if they belong to a previous day
+    pub fn rollover(&mut self) {
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        if self.date != today {
+            *self = Self {
+                date: today,
+                ..Default::default()
+            };
+        }
+    }
+
+    /// Records that the key has been handed out for a request
+    pub fn record_dispatch(&mut self) {
+        self.rollover();
+        self.requests += 1;
+    }
+
+    /// Records the token usage of a response
+    ///
+    /// # Arguments
+    /// * `usage` - Token usage reported by Gemini
+    pub fn record_usage(&mut self, usage: KeyUsage) {
+        self.rollover();
+        self.prompt_tokens += usage.prompt_tokens;
+        self.candidate_tokens += usage.candidate_tokens;
+        self.thought_tokens += usage.thought_tokens;
+    }
+}
+
+/// Failure of a request that affects the state of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFailure {
+    /// 429 RESOURCE_EXHAUSTED, with the reset time parsed from the response if any
+    RateLimited(Option<i64>),
+    /// 403 or 400 API_KEY_INVALID
+    Unauthorized,
+}
+
+/// A key that has been moved out of rotation
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UselessKey {
+    pub key: GeminiKey,
+    #[serde(default)]
+    pub count_403: u32,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl PartialEq for UselessKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+impl Eq for UselessKey {}
+impl std::hash::Hash for UselessKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key.hash(state);
+    }
+}
+
+impl UselessKey {
+    /// Creates an invalid key from a key status
+    ///
+    /// # Arguments
+    /// * `status` - The key to invalidate
+    /// * `reason` - Why the key was invalidated
+    pub fn new(status: KeyStatus, reason: impl Into<String>) -> Self {
+        Self {
+            key: status.key,
+            count_403: status.count_403,
+            reason: reason.into(),
+        }
+    }
 }
 
 impl PartialEq for KeyStatus {
@@ -88,4 +199,19 @@ impl KeyStatus {
     pub fn validate(&self) -> bool {
         self.key.validate()
     }
+
+    /// Checks if the key's cooldown has expired
+    /// If the reset time has passed, sets it to None so the key becomes valid again
+    ///
+    /// # Returns
+    /// The same KeyStatus with potentially updated reset_time
+    pub fn reset(self) -> Self {
+        match self.reset_time {
+            Some(t) if t < chrono::Utc::now().timestamp() => Self {
+                reset_time: None,
+                ..self
+            },
+            _ => self,
+        }
+    }
 }
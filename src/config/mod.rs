@@ -3,10 +3,16 @@ mod clewdr_config;
 mod constants;
 mod cookie;
 mod reason;
+mod state;
 mod key;
+mod strategy;
+mod vertex;
 
 pub use clewdr_config::*;
 pub use constants::*;
 pub use cookie::*;
 pub use reason::*;
+pub use state::*;
 pub use key::*;
+pub use strategy::*;
+pub use vertex::*;
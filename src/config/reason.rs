@@ -8,7 +8,7 @@ use thiserror::Error;
 
 use crate::config::ClewdrCookie;
 
-use super::CookieStatus;
+use super::{AccountInfo, CookieStats, CookieStatus};
 
 /// Reason why a cookie is considered useless
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Error)]
@@ -52,6 +52,10 @@ impl Display for Reason {
 pub struct UselessCookie {
     pub cookie: ClewdrCookie,
     pub reason: Reason,
+    #[serde(default)]
+    pub stats: CookieStats,
+    #[serde(default)]
+    pub account: Option<AccountInfo>,
 }
 
 impl PartialEq<CookieStatus> for UselessCookie {
@@ -84,6 +88,42 @@ impl UselessCookie {
     /// # Returns
     /// A new UselessCookie instance
     pub fn new(cookie: ClewdrCookie, reason: Reason) -> Self {
-        Self { cookie, reason }
+        Self {
+            cookie,
+            reason,
+            stats: CookieStats::default(),
+            account: None,
+        }
+    }
+
+    /// Creates a new UselessCookie instance keeping the statistics of the cookie
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie status that is unusable
+    /// * `reason` - The reason why the cookie is unusable
+    ///
+    /// # Returns
+    /// A new UselessCookie instance
+    pub fn from_status(cookie: CookieStatus, reason: Reason) -> Self {
+        Self {
+            cookie: cookie.cookie,
+            reason,
+            stats: cookie.stats,
+            account: cookie.account,
+        }
+    }
+
+    /// Converts the unusable cookie back into a cookie status for another check
+    /// The statistics and account information are kept
+    ///
+    /// # Returns
+    /// A cookie status without reset time
+    pub fn to_status(&self) -> CookieStatus {
+        CookieStatus {
+            cookie: self.cookie.to_owned(),
+            stats: self.stats.to_owned(),
+            account: self.account.to_owned(),
+            ..Default::default()
+        }
     }
 }
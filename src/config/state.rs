@@ -0,0 +1,120 @@
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, io::Write};
+use tracing::{error, info};
+
+use crate::{
+    config::{
+        CLEWDR_CONFIG, ClewdrConfig, CookieStatus, KeyStatus, STATE_PATH, UselessCookie,
+        UselessKey, VertexCredential,
+    },
+    error::ClewdrError,
+};
+
+/// Persistent state of the cookie and key pools
+///
+/// Stored in its own file next to `clewdr.toml`, so frequent pool updates
+/// do not rewrite the user configuration.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PoolState {
+    #[serde(default)]
+    pub cookie_array: HashSet<CookieStatus>,
+    #[serde(default)]
+    pub wasted_cookie: HashSet<UselessCookie>,
+    #[serde(default)]
+    pub gemini_keys: HashSet<KeyStatus>,
+    #[serde(default)]
+    pub wasted_keys: HashSet<UselessKey>,
+    #[serde(default)]
+    pub vertex_credentials: HashSet<VertexCredential>,
+}
+
+impl PoolState {
+    /// Loads the pool state from the state file
+    ///
+    /// Cookies and keys still found in the configuration, either from an older
+    /// `clewdr.toml` or from the cookie file argument, are moved into the state,
+    /// and removed from the configuration afterwards.
+    ///
+    /// # Returns
+    /// * `PoolState` - The loaded state, empty if the file does not exist
+    pub fn load() -> Self {
+        let mut state = std::fs::read_to_string(STATE_PATH.as_path())
+            .ok()
+            .and_then(|s| {
+                serde_json::from_str::<PoolState>(&s)
+                    .inspect_err(|e| error!("Failed to parse state file: {}", e))
+                    .ok()
+            })
+            .unwrap_or_default();
+        state.cookie_array = state.cookie_array.into_iter().map(|c| c.reset()).collect();
+        state.gemini_keys = state.gemini_keys.into_iter().map(|k| k.reset()).collect();
+        state.vertex_credentials = state
+            .vertex_credentials
+            .into_iter()
+            .map(|c| c.reset())
+            .collect();
+
+        let config = CLEWDR_CONFIG.load();
+        if config.cookie_array.is_empty()
+            && config.wasted_cookie.is_empty()
+            && config.gemini_keys.is_empty()
+        {
+            return state;
+        }
+        let before = state.cookie_array.len() + state.wasted_cookie.len() + state.gemini_keys.len();
+        for cookie in config.cookie_array.iter() {
+            if !state.wasted_cookie.iter().any(|c| c == cookie) {
+                state.cookie_array.insert(cookie.to_owned());
+            }
+        }
+        for cookie in config.wasted_cookie.iter() {
+            if !state.cookie_array.iter().any(|c| cookie == c) {
+                state.wasted_cookie.insert(cookie.to_owned());
+            }
+        }
+        state.gemini_keys.extend(config.gemini_keys.iter().cloned());
+        let after = state.cookie_array.len() + state.wasted_cookie.len() + state.gemini_keys.len();
+        info!(
+            "Migrated {} cookies and keys from config to {}",
+            (after - before).to_string().green(),
+            STATE_PATH.display()
+        );
+        // write the state before removing the entries from the config
+        if let Err(e) = state.save() {
+            error!("Failed to save state: {}", e);
+            return state;
+        }
+        CLEWDR_CONFIG.rcu(|config| {
+            let mut config = ClewdrConfig::clone(config);
+            config.cookie_array.clear();
+            config.wasted_cookie.clear();
+            config.gemini_keys.clear();
+            config
+        });
+        CLEWDR_CONFIG.load().save().unwrap_or_else(|e| {
+            error!("Failed to save config: {}", e);
+        });
+        state
+    }
+
+    /// Saves the pool state atomically
+    /// Writes to a temporary file first and renames it over the state file,
+    /// so a crash never leaves a partially written file behind
+    ///
+    /// # Returns
+    /// * `Result<(), ClewdrError>` - Success or an IO or serialization error
+    pub fn save(&self) -> Result<(), ClewdrError> {
+        #[cfg(feature = "no_fs")]
+        {
+            return Ok(());
+        }
+        let data = serde_json::to_vec_pretty(self)?;
+        let tmp = STATE_PATH.with_extension("json.tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, STATE_PATH.as_path())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+/// Strategy used by the cookie manager to choose the next cookie to dispatch
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DispatchStrategy {
+    /// Rotate through the valid cookies in order
+    #[default]
+    RoundRobin,
+    /// Pick the cookie that has not been used for the longest time
+    LeastRecentlyUsed,
+    /// Pick the cookie serving the fewest requests at the moment
+    LeastInFlight,
+    /// Pick a random cookie, weighted by the weight of each cookie
+    RandomWeighted,
+    /// Pick the cookie with the best plan, Max first, then Pro, cookies of unknown plan rank as Free
+    PreferCapabilities,
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use yup_oauth2::ServiceAccountKey;
+
+/// A Vertex service account in the credential pool
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VertexCredential {
+    pub credential: ServiceAccountKey,
+    /// Model used with this credential instead of the requested one
+    #[serde(default)]
+    pub model_id: Option<String>,
+    /// Location of the Vertex endpoint, overrides the configured location
+    #[serde(default)]
+    pub location: Option<String>,
+    /// Number of failed requests since the last cooldown
+    #[serde(default)]
+    pub count_failure: u32,
+    /// Timestamp at which a credential on cooldown can be used again
+    #[serde(default)]
+    pub reset_time: Option<i64>,
+}
+
+impl PartialEq for VertexCredential {
+    fn eq(&self, other: &Self) -> bool {
+        self.credential.client_email == other.credential.client_email
+    }
+}
+
+impl Eq for VertexCredential {}
+
+impl Hash for VertexCredential {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.credential.client_email.hash(state);
+    }
+}
+
+impl VertexCredential {
+    /// Creates a pool entry from a service account key without overrides
+    ///
+    /// # Arguments
+    /// * `credential` - The service account key
+    /// * `model_id` - Model used with the credential, if any
+    pub fn new(credential: ServiceAccountKey, model_id: Option<String>) -> Self {
+        Self {
+            credential,
+            model_id,
+            location: None,
+            count_failure: 0,
+            reset_time: None,
+        }
+    }
+
+    /// Gets the identifier of the credential
+    pub fn id(&self) -> &str {
+        &self.credential.client_email
+    }
+
+    /// Gets the project of the credential
+    pub fn project_id(&self) -> &str {
+        self.credential.project_id.as_deref().unwrap_or_default()
+    }
+
+    /// Checks if the credential's cooldown has expired
+    /// If the reset time has passed, clears it and the failure count
+    ///
+    /// # Returns
+    /// The same VertexCredential with potentially updated reset_time
+    pub fn reset(self) -> Self {
+        match self.reset_time {
+            Some(t) if t < chrono::Utc::now().timestamp() => Self {
+                reset_time: None,
+                count_failure: 0,
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Describes the credential without its private key
+    pub fn info(&self) -> VertexCredentialInfo {
+        VertexCredentialInfo {
+            client_email: self.credential.client_email.to_owned(),
+            project_id: self.credential.project_id.to_owned(),
+            private_key_id: self.credential.private_key_id.to_owned(),
+            model_id: self.model_id.to_owned(),
+            location: self.location.to_owned(),
+            count_failure: self.count_failure,
+            reset_time: self.reset_time,
+        }
+    }
+}
+
+/// Public information of a Vertex credential, safe to return from the admin API
+#[derive(Debug, Serialize, Clone)]
+pub struct VertexCredentialInfo {
+    pub client_email: String,
+    pub project_id: Option<String>,
+    pub private_key_id: Option<String>,
+    pub model_id: Option<String>,
+    pub location: Option<String>,
+    pub count_failure: u32,
+    pub reset_time: Option<i64>,
+}
@@ -15,7 +15,7 @@ use tracing::{debug, error};
 
 use crate::{
     config::Reason,
-    services::{cookie_manager::CookieEvent, key_manager::KeyEvent},
+    services::{cookie_manager::CookieEvent, key_manager::KeyEvent, vertex_manager::VertexEvent},
     types::claude_message::Message,
 };
 
@@ -33,6 +33,10 @@ pub enum ClewdrError {
     YuOAuth2Error { source: yup_oauth2::Error },
     #[snafu(display("Empty choices"))]
     EmptyChoices,
+    #[snafu(display("Upstream stream error: {}: {}", type_, message))]
+    UpstreamStreamError { type_: String, message: String },
+    #[snafu(display("Response truncated by the upstream"))]
+    TruncatedResponse,
     #[snafu(display("JSON error: {}", source))]
     #[snafu(context(false))]
     JsonError { source: serde_json::Error },
@@ -61,12 +65,20 @@ pub enum ClewdrError {
     #[snafu(display("Key send error: {}", source))]
     #[snafu(context(false))]
     KeySendError {
-        source: tokio::sync::mpsc::error::SendError<KeyEvent>,
+        #[snafu(source(from(tokio::sync::mpsc::error::SendError<KeyEvent>, Box::new)))]
+        source: Box<tokio::sync::mpsc::error::SendError<KeyEvent>>,
+    },
+    #[snafu(display("Vertex send error: {}", source))]
+    #[snafu(context(false))]
+    VertexSendError {
+        #[snafu(source(from(tokio::sync::mpsc::error::SendError<VertexEvent>, Box::new)))]
+        source: Box<tokio::sync::mpsc::error::SendError<VertexEvent>>,
     },
     #[snafu(display("Cookie send error: {}", source))]
     #[snafu(context(false))]
     CookieSendError {
-        source: tokio::sync::mpsc::error::SendError<CookieEvent>,
+        #[snafu(source(from(tokio::sync::mpsc::error::SendError<CookieEvent>, Box::new)))]
+        source: Box<tokio::sync::mpsc::error::SendError<CookieEvent>>,
     },
     #[snafu(display("Retries exceeded"))]
     TooManyRetries,
@@ -92,6 +104,8 @@ pub enum ClewdrError {
     NoCookieAvailable,
     #[snafu(display("No key available"))]
     NoKeyAvailable,
+    #[snafu(display("No Vertex credential available"))]
+    NoVertexAvailable,
     #[snafu(display("Invalid Cookie: {}", reason))]
     #[snafu(context(false))]
     InvalidCookie {
@@ -183,6 +197,9 @@ impl IntoResponse for ClewdrError {
                 (StatusCode::BAD_REQUEST, json!(self.to_string()))
             }
             ClewdrError::EmptyChoices => (StatusCode::NO_CONTENT, json!(self.to_string())),
+            ClewdrError::UpstreamStreamError { .. } | ClewdrError::TruncatedResponse => {
+                (StatusCode::BAD_GATEWAY, json!(self.to_string()))
+            }
             _ => (StatusCode::INTERNAL_SERVER_ERROR, json!(self.to_string())),
         };
         let err = ClaudeError {
@@ -6,8 +6,7 @@ use axum::{
 };
 use bytes::Bytes;
 use colored::Colorize;
-use futures::{Stream, future::Either, stream};
-use hyper_util::client::legacy::connect::HttpConnector;
+use futures::{Stream, StreamExt, future::Either, stream};
 use rquest::{Client, ClientBuilder, header::AUTHORIZATION};
 use serde::Serialize;
 use serde_json::Value;
@@ -15,16 +14,17 @@ use snafu::ResultExt;
 use strum::Display;
 use tokio::spawn;
 use tracing::{Instrument, Level, error, info, span, warn};
-use yup_oauth2::{CustomHyperClientBuilder, ServiceAccountAuthenticator, ServiceAccountKey};
 
 use crate::{
-    config::{CLEWDR_CONFIG, GEMINI_ENDPOINT, KeyStatus},
-    error::{CheckGeminiErr, ClewdrError, InvalidUriSnafu, RquestSnafu},
+    config::{CLEWDR_CONFIG, GEMINI_ENDPOINT, KeyFailure, KeyStatus, KeyUsage, VertexCredential},
+    error::{CheckGeminiErr, ClewdrError, RquestSnafu},
     gemini_body::GeminiArgs,
     middleware::gemini::GeminiContext,
     services::{
         cache::{CACHE, GetHashKey},
         key_manager::KeyEventSender,
+        token_cache::VERTEX_TOKENS,
+        vertex_manager::VertexEventSender,
     },
     types::gemini::response::{FinishReason, GeminiResponse},
 };
@@ -37,48 +37,17 @@ pub enum GeminiApiFormat {
 
 static DUMMY_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
 
-async fn get_token(sa_key: ServiceAccountKey) -> Result<String, ClewdrError> {
-    const SCOPES: [&str; 1] = ["https://www.googleapis.com/auth/cloud-platform"];
-    let token = if let Some(proxy) = CLEWDR_CONFIG.load().proxy.to_owned() {
-        let proxy = proxy
-            .trim_start_matches("http://")
-            .trim_start_matches("https://")
-            .trim_start_matches("socks5://");
-        let proxy = format!("http://{}", proxy);
-        let proxy_uri = proxy.parse().context(InvalidUriSnafu {
-            uri: proxy.to_owned(),
-        })?;
-        let proxy = hyper_http_proxy::Proxy::new(hyper_http_proxy::Intercept::All, proxy_uri);
-        let connector = HttpConnector::new();
-        let proxy_connector = hyper_http_proxy::ProxyConnector::from_proxy(connector, proxy)?;
-        let client =
-            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
-                .pool_max_idle_per_host(0)
-                .build(proxy_connector);
-        let client_builder = CustomHyperClientBuilder::from(client);
-        let auth = ServiceAccountAuthenticator::with_client(sa_key, client_builder)
-            .build()
-            .await?;
-        auth.token(&SCOPES).await?
-    } else {
-        let auth = ServiceAccountAuthenticator::builder(sa_key).build().await?;
-        auth.token(&SCOPES).await?
-    };
-    let token = token.token().ok_or(ClewdrError::UnexpectedNone {
-        msg: "Oauth token is None",
-    })?;
-    Ok(token.into())
-}
-
 #[derive(Clone)]
 pub struct GeminiState {
     pub model: String,
     pub vertex: bool,
     pub path: String,
     pub key: Option<KeyStatus>,
+    pub credential: Option<VertexCredential>,
     pub stream: bool,
     pub query: GeminiArgs,
     pub event_sender: KeyEventSender,
+    pub vertex_sender: VertexEventSender,
     pub api_format: GeminiApiFormat,
     pub client: Client,
     pub cache_key: Option<(u64, usize)>,
@@ -86,7 +55,7 @@ pub struct GeminiState {
 
 impl GeminiState {
     /// Create a new AppState instance
-    pub fn new(tx: KeyEventSender) -> Self {
+    pub fn new(tx: KeyEventSender, vertex_tx: VertexEventSender) -> Self {
         GeminiState {
             model: String::new(),
             vertex: false,
@@ -94,17 +63,31 @@ impl GeminiState {
             query: GeminiArgs::default(),
             stream: false,
             key: None,
+            credential: None,
             event_sender: tx,
+            vertex_sender: vertex_tx,
             api_format: GeminiApiFormat::Gemini,
             client: DUMMY_CLIENT.to_owned(),
             cache_key: None,
         }
     }
 
-    pub async fn report_403(&self) -> Result<(), ClewdrError> {
-        if let Some(mut key) = self.key.to_owned() {
-            key.count_403 += 1;
-            self.event_sender.return_key(key).await?;
+    pub async fn report_failure(&self, failure: KeyFailure) -> Result<(), ClewdrError> {
+        if let Some(key) = self.key.to_owned() {
+            self.event_sender.return_key(key, Some(failure)).await?;
+        }
+        if let Some(credential) = self.credential.as_ref() {
+            self.vertex_sender
+                .return_credential(credential.id().to_owned(), Some(failure))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reports that the key served a request, which clears its unauthorized responses
+    pub async fn report_success(&self) -> Result<(), ClewdrError> {
+        if let Some(key) = self.key.to_owned() {
+            self.event_sender.return_key(key, None).await?;
         }
         Ok(())
     }
@@ -152,25 +135,31 @@ impl GeminiState {
             "generateContent"
         };
 
-        // Get an access token
-        let Some(cred) = CLEWDR_CONFIG.load().vertex.credential.to_owned() else {
-            return Err(ClewdrError::BadRequest {
-                msg: "Vertex credential not found",
-            });
-        };
+        // Get a credential from the pool and an access token
+        let cred = self.vertex_sender.request().await?;
+        self.credential = Some(cred.to_owned());
+        info!("[VERTEX] {}", cred.id().green());
 
-        let access_token = get_token(cred.to_owned()).await?;
+        let access_token = match VERTEX_TOKENS.get(&cred.credential).await {
+            Ok(token) => token,
+            Err(e) => {
+                self.report_failure(KeyFailure::Unauthorized).await?;
+                return Err(e);
+            }
+        };
         let bearer = format!("Bearer {}", access_token);
+        let model = cred.model_id.as_deref().unwrap_or(&self.model);
+        let vertex = &CLEWDR_CONFIG.load().vertex;
+        let location = vertex.location_for(model, &cred);
+        let base_url = vertex.endpoint(&location);
         let res = match self.api_format {
             GeminiApiFormat::Gemini => {
                 let endpoint = format!(
-                    "https://aiplatform.googleapis.com/v1/projects/{}/locations/global/publishers/google/models/{}:{method}",
-                    cred.project_id.unwrap_or_default(),
-                    self.model
+                    "{base_url}/v1/projects/{}/locations/{location}/publishers/google/models/{model}:{method}",
+                    cred.project_id(),
                 );
                 let query_vec = self.query.to_vec();
-                self
-                    .client
+                self.client
                     .post(endpoint)
                     .query(&query_vec)
                     .header(AUTHORIZATION, bearer)
@@ -182,13 +171,17 @@ impl GeminiState {
                     })?
             }
             GeminiApiFormat::OpenAI => {
+                let mut body = serde_json::to_value(&p)?;
+                if let Some(model) = cred.model_id.as_ref() {
+                    body["model"] = format!("google/{}", model).into();
+                }
                 self.client
                     .post(format!(
-                        "https://aiplatform.googleapis.com/v1beta1/projects/{}/locations/global/endpoints/openapi/chat/completions",
-                        cred.project_id.unwrap_or_default(),
+                        "{base_url}/v1beta1/projects/{}/locations/{location}/endpoints/openapi/chat/completions",
+                        cred.project_id(),
                     ))
                     .header(AUTHORIZATION, bearer)
-                    .json(&p)
+                    .json(&body)
                     .send()
                     .await
                     .context(RquestSnafu {
@@ -196,7 +189,16 @@ impl GeminiState {
                     })?
             }
         };
-        let res = res.check_gemini().await?;
+        let res = match res.check_gemini().await {
+            Ok(res) => res,
+            Err(e) => {
+                if matches!(e, ClewdrError::GeminiHttpError { code, .. } if code.as_u16() == 401) {
+                    // the token was rejected before its expiry
+                    VERTEX_TOKENS.invalidate(&cred.credential).await;
+                }
+                return Err(e);
+            }
+        };
         Ok(res)
     }
 
@@ -267,6 +269,10 @@ impl GeminiState {
                         err = Some(ClewdrError::EmptyChoices);
                         continue;
                     };
+                    state.report_success().await.unwrap_or_else(|e| {
+                        error!("Failed to report key success: {}", e);
+                    });
+                    let stream = state.track_usage(stream);
                     let res = transform_response(self.cache_key, stream).await;
                     return Ok(res);
                 }
@@ -277,11 +283,11 @@ impl GeminiState {
                         error!("{}", e);
                     }
                     match e {
-                        ClewdrError::GeminiHttpError { code, .. } => {
-                            if code == 403 {
+                        ClewdrError::GeminiHttpError { code, ref inner } => {
+                            if let Some(failure) = key_failure(code.as_u16(), inner) {
                                 spawn(async move {
-                                    state.report_403().await.unwrap_or_else(|e| {
-                                        error!("Failed to report 403: {}", e);
+                                    state.report_failure(failure).await.unwrap_or_else(|e| {
+                                        error!("Failed to report key failure: {}", e);
                                     });
                                 });
                             }
@@ -319,6 +325,30 @@ impl GeminiState {
         None
     }
 
+    /// Collects the response body to attribute its token usage to the key
+    /// The usage is reported once the stream is finished or dropped
+    ///
+    /// # Arguments
+    /// * `input` - The response stream
+    ///
+    /// # Returns
+    /// The same stream
+    fn track_usage(
+        &self,
+        input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
+    ) -> impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static {
+        let mut tracker = self.key.to_owned().map(|key| UsageTracker {
+            key,
+            sender: self.event_sender.to_owned(),
+            body: Vec::new(),
+        });
+        input.inspect(move |chunk| {
+            if let (Some(tracker), Ok(bytes)) = (tracker.as_mut(), chunk) {
+                tracker.body.extend_from_slice(bytes);
+            }
+        })
+    }
+
     async fn check_empty_choices(
         &self,
         resp: rquest::Response,
@@ -355,6 +385,116 @@ impl GeminiState {
     }
 }
 
+/// Body of a response served with a key, its usage is reported when dropped
+struct UsageTracker {
+    key: KeyStatus,
+    sender: KeyEventSender,
+    body: Vec<u8>,
+}
+
+impl Drop for UsageTracker {
+    fn drop(&mut self) {
+        let Some(usage) = parse_usage(&self.body) else {
+            return;
+        };
+        self.sender
+            .record_usage(self.key.to_owned(), usage)
+            .unwrap_or_else(|e| {
+                error!("Failed to record key usage: {}", e);
+            });
+    }
+}
+
+/// Extracts the token usage from a response body
+/// Handles native and OpenAI formats, as a single JSON value,
+/// a streamed JSON array or server sent events
+///
+/// # Arguments
+/// * `body` - The complete response body
+///
+/// # Returns
+/// * `Option<KeyUsage>` - The last usage found in the body
+fn parse_usage(body: &[u8]) -> Option<KeyUsage> {
+    if let Ok(value) = serde_json::from_slice::<Value>(body) {
+        return match value {
+            Value::Array(values) => values.iter().rev().find_map(usage_of),
+            value => usage_of(&value),
+        };
+    }
+    // usage metadata is cumulative, so the last event holds the totals
+    String::from_utf8_lossy(body)
+        .lines()
+        .rev()
+        .filter_map(|l| l.strip_prefix("data:"))
+        .filter_map(|l| serde_json::from_str::<Value>(l.trim()).ok())
+        .find_map(|v| usage_of(&v))
+}
+
+/// Reads the token usage of a single response or chunk
+fn usage_of(value: &Value) -> Option<KeyUsage> {
+    let count = |v: &Value| v.as_u64().unwrap_or_default();
+    let meta = &value["usageMetadata"];
+    if meta.is_object() {
+        return Some(KeyUsage {
+            prompt_tokens: count(&meta["promptTokenCount"]),
+            candidate_tokens: count(&meta["candidatesTokenCount"]),
+            thought_tokens: count(&meta["thoughtsTokenCount"]),
+        });
+    }
+    let usage = &value["usage"];
+    if usage.is_object() {
+        let prompt_tokens = count(&usage["prompt_tokens"]);
+        let candidate_tokens = count(&usage["completion_tokens"]);
+        // thoughts are only included in the total tokens
+        let thought_tokens = usage["completion_tokens_details"]["reasoning_tokens"]
+            .as_u64()
+            .unwrap_or_else(|| {
+                count(&usage["total_tokens"]).saturating_sub(prompt_tokens + candidate_tokens)
+            });
+        return Some(KeyUsage {
+            prompt_tokens,
+            candidate_tokens,
+            thought_tokens,
+        });
+    }
+    None
+}
+
+/// Classifies a Gemini error response into a failure of the key used
+///
+/// # Arguments
+/// * `code` - HTTP status code of the response
+/// * `body` - Parsed error body of the response
+///
+/// # Returns
+/// * `Option<KeyFailure>` - The failure, or None if the key is not to blame
+pub(crate) fn key_failure(code: u16, body: &Value) -> Option<KeyFailure> {
+    // the OpenAI compatible endpoint wraps the error in an array
+    let body = body.as_array().and_then(|a| a.first()).unwrap_or(body);
+    let details = body["error"]["details"].as_array();
+    match code {
+        429 => {
+            // e.g. "retryDelay": "37s"
+            let delay = details
+                .into_iter()
+                .flatten()
+                .find_map(|d| d["retryDelay"].as_str())
+                .and_then(|d| d.trim_end_matches('s').parse::<f64>().ok());
+            let reset_time = delay.map(|d| chrono::Utc::now().timestamp() + d.ceil() as i64);
+            Some(KeyFailure::RateLimited(reset_time))
+        }
+        403 => Some(KeyFailure::Unauthorized),
+        400 if details
+            .into_iter()
+            .flatten()
+            .any(|d| d["reason"].as_str() == Some("API_KEY_INVALID")) =>
+        {
+            Some(KeyFailure::Unauthorized)
+        }
+        _ => None,
+    }
+}
+
 async fn transform_response(
     cache_key: Option<(u64, usize)>,
     input: impl Stream<Item = Result<Bytes, rquest::Error>> + Send + 'static,
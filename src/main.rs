@@ -91,15 +91,18 @@ async fn main() -> Result<(), ClewdrError> {
     // create a TCP listener
     let addr = CLEWDR_CONFIG.load().address();
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    let router = clewdr::router::RouterBuilder::new()
-        .with_default_setup()
-        .build();
+    let builder = clewdr::router::RouterBuilder::new().with_default_setup();
+    let writer = builder.state_writer();
+    let router = builder.build();
     // serve the application
-    Ok(axum::serve(listener, router)
+    axum::serve(listener, router)
         .with_graceful_shutdown(async {
             tokio::signal::ctrl_c()
                 .await
                 .expect("Failed to install Ctrl-C handler");
         })
-        .await?)
+        .await?;
+    // write the pool state changes still waiting for the debounce
+    writer.flush().await;
+    Ok(())
 }
@@ -28,6 +28,13 @@ where
     }
 }
 
+/// Cookie groups the authenticated user API key is restricted to
+///
+/// Inserted into the request extensions by the user authentication guards,
+/// None if the key may use every cookie.
+#[derive(Clone, Debug, Default)]
+pub struct KeyGroups(pub Option<Vec<String>>);
+
 pub struct RequireQueryKeyAuth;
 impl<S> FromRequestParts<S> for RequireQueryKeyAuth
 where
@@ -55,6 +62,8 @@ where
 /// # Example
 ///
 /// ```
+/// # use axum::response::IntoResponse;
+/// # use clewdr::middleware::RequireAdminAuth;
 /// async fn admin_only_handler(
 ///     _: RequireAdminAuth,
 ///     // other extractors...
@@ -92,8 +101,10 @@ where
 /// # Example
 ///
 /// ```
+/// # use axum::response::IntoResponse;
+/// # use clewdr::middleware::RequireBearerAuth;
 /// async fn openai_handler(
-///     _: RequireOaiAuth,
+///     _: RequireBearerAuth,
 ///     // other extractors...
 /// ) -> impl IntoResponse {
 ///     // This handler only executes if OpenAI authentication succeeds
@@ -117,6 +128,9 @@ where
             warn!("Invalid Bearer key: {}", key);
             return Err(ClewdrError::InvalidKey);
         }
+        parts
+            .extensions
+            .insert(KeyGroups(CLEWDR_CONFIG.load().key_groups(&key)));
         Ok(Self)
     }
 }
@@ -140,6 +154,9 @@ where
             warn!("Invalid x-api-key: {}", key);
             return Err(ClewdrError::InvalidKey);
         }
+        parts
+            .extensions
+            .insert(KeyGroups(CLEWDR_CONFIG.load().key_groups(&key)));
         Ok(Self)
     }
 }
@@ -1,4 +1,6 @@
+use serde_json::{Value, json};
 use std::sync::LazyLock;
+use tracing::warn;
 
 use axum::{
     Json,
@@ -9,7 +11,7 @@ use axum::{
 use crate::{
     claude_state::{ClaudeApiFormat, ClaudeState},
     error::ClewdrError,
-    types::claude_message::{ContentBlock, CreateMessageParams, Message, Role},
+    types::claude_message::{ContentBlock, CreateMessageParams, Message, Role, Thinking},
 };
 
 use super::to_oai;
@@ -43,6 +45,10 @@ pub struct ClaudeContext {
     pub api_format: ClaudeApiFormat,
     /// The stop sequence used for the request
     pub stop_sequences: Vec<String>,
+    /// The model requested by the client
+    pub model: String,
+    /// Whether to send the usage at the end of an OpenAI stream
+    pub include_usage: bool,
 }
 
 /// Predefined test message in Claude format for connection testing
@@ -67,12 +73,33 @@ impl FromRequest<ClaudeState> for ClaudePreprocess {
 
     async fn from_request(req: Request, state: &ClaudeState) -> Result<Self, Self::Rejection> {
         let uri = req.uri().to_string();
-        let Json(mut body) = Json::<CreateMessageParams>::from_request(req, &()).await?;
+        let format = if uri.contains("chat/completions") {
+            ClaudeApiFormat::OpenAI
+        } else {
+            ClaudeApiFormat::Claude
+        };
+        let mut body = match format {
+            ClaudeApiFormat::Claude => {
+                let Json(body) = Json::<CreateMessageParams>::from_request(req, &()).await?;
+                body
+            }
+            ClaudeApiFormat::OpenAI => {
+                let Json(mut body) = Json::<Value>::from_request(req, &()).await?;
+                from_oai_tools(&mut body);
+                from_oai_reasoning(&mut body);
+                serde_json::from_value::<CreateMessageParams>(body).map_err(|e| {
+                    warn!("Failed to parse OpenAI request: {}", e);
+                    ClewdrError::BadRequest {
+                        msg: "Invalid OpenAI request body",
+                    }
+                })?
+            }
+        };
 
         // Handle thinking mode by modifying the model name
         if body.model.ends_with("-thinking") {
             body.model = body.model.trim_end_matches("-thinking").to_string();
-            body.thinking = Some(Default::default());
+            body.thinking = Some(Thinking::new(DEFAULT_THINKING_BUDGET));
         }
 
         // Check for test messages and respond appropriately
@@ -84,13 +111,8 @@ impl FromRequest<ClaudeState> for ClaudePreprocess {
             return Err(ClewdrError::TestMessage);
         }
 
-        // Determine streaming status and API format
+        // Determine streaming status
         let stream = body.stream.unwrap_or_default();
-        let format = if uri.contains("chat/completions") {
-            ClaudeApiFormat::OpenAI
-        } else {
-            ClaudeApiFormat::Claude
-        };
 
         // Update state with format information
         let mut state = state.to_owned();
@@ -104,6 +126,11 @@ impl FromRequest<ClaudeState> for ClaudePreprocess {
             stream,
             api_format: format,
             stop_sequences: stop,
+            model: body.model.to_owned(),
+            include_usage: body
+                .stream_options
+                .as_ref()
+                .is_some_and(|o| o.include_usage),
         };
 
         // Try to retrieve from cache before processing
@@ -116,3 +143,117 @@ impl FromRequest<ClaudeState> for ClaudePreprocess {
         Ok(Self(body, info))
     }
 }
+
+/// Thinking budget of requests enabling the thinking mode with a `-thinking` model suffix
+const DEFAULT_THINKING_BUDGET: u64 = 8192;
+
+/// Converts the `reasoning_effort` of an OpenAI request into a Claude thinking mode
+///
+/// An explicit `thinking` field takes precedence. `none` disables thinking,
+/// other efforts enable it with a budget growing with the effort.
+///
+/// # Arguments
+/// * `body` - The OpenAI request body, modified in place
+fn from_oai_reasoning(body: &mut Value) {
+    let Some(body) = body.as_object_mut() else {
+        return;
+    };
+    if body.get("thinking").is_some_and(|t| !t.is_null()) {
+        return;
+    }
+    let thinking = match body.get("reasoning_effort").and_then(|e| e.as_str()) {
+        Some("none") => json!({ "type": "disabled" }),
+        Some("minimal" | "low") => json!({ "type": "enabled", "budget_tokens": 1024 }),
+        Some("medium") => json!({ "type": "enabled", "budget_tokens": 8192 }),
+        Some("high") => json!({ "type": "enabled", "budget_tokens": 24576 }),
+        _ => return,
+    };
+    body.insert("thinking".to_string(), thinking);
+}
+
+/// Converts the tool fields of an OpenAI request into their Claude equivalents
+///
+/// - `tools` with `function` definitions become Claude tools
+/// - `tool_choice` strings and objects become Claude tool choices
+/// - `tool_calls` of assistant messages become `tool_use` blocks
+/// - `tool` messages become user messages with `tool_result` blocks
+///
+/// # Arguments
+/// * `body` - The OpenAI request body, modified in place
+fn from_oai_tools(body: &mut Value) {
+    if let Some(tools) = body["tools"].as_array_mut() {
+        for tool in tools.iter_mut() {
+            let function = &tool["function"];
+            if function.is_null() {
+                continue;
+            }
+            *tool = json!({
+                "name": function["name"],
+                "description": function["description"],
+                "input_schema": match &function["parameters"] {
+                    Value::Null => json!({ "type": "object" }),
+                    parameters => parameters.to_owned(),
+                },
+            });
+        }
+    }
+    let choice = match &body["tool_choice"] {
+        Value::String(s) if s == "none" => Some(json!({ "type": "none" })),
+        Value::String(s) if s == "required" => Some(json!({ "type": "any" })),
+        Value::String(_) => Some(json!({ "type": "auto" })),
+        Value::Object(o) if o.contains_key("function") => {
+            Some(json!({ "type": "tool", "name": o["function"]["name"] }))
+        }
+        _ => None,
+    };
+    if let Some(choice) = choice {
+        body["tool_choice"] = choice;
+    }
+    let Some(messages) = body["messages"].as_array_mut() else {
+        return;
+    };
+    for msg in messages.iter_mut() {
+        match msg["role"].as_str() {
+            Some("tool") => {
+                *msg = json!({
+                    "role": "user",
+                    "content": [{
+                        "type": "tool_result",
+                        "tool_use_id": msg["tool_call_id"],
+                        "content": match &msg["content"] {
+                            Value::Null => json!(""),
+                            content => content.to_owned(),
+                        },
+                    }],
+                });
+            }
+            Some("developer") => msg["role"] = json!("system"),
+            _ => {}
+        }
+        if msg["content"].is_null() {
+            msg["content"] = json!("");
+        }
+        let Some(calls) = msg["tool_calls"].as_array() else {
+            continue;
+        };
+        let mut blocks = match &msg["content"] {
+            Value::String(s) if s.trim().is_empty() => vec![],
+            Value::String(s) => vec![json!({ "type": "text", "text": s })],
+            Value::Array(a) => a.to_owned(),
+            _ => vec![],
+        };
+        blocks.extend(calls.iter().map(|call| {
+            let arguments = &call["function"]["arguments"];
+            json!({
+                "type": "tool_use",
+                "id": call["id"],
+                "name": call["function"]["name"],
+                "input": arguments
+                    .as_str()
+                    .and_then(|a| serde_json::from_str::<Value>(a).ok())
+                    .unwrap_or_else(|| json!({})),
+            })
+        }));
+        *msg = json!({ "role": msg["role"], "content": blocks });
+    }
+}
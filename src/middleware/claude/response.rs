@@ -1,11 +1,21 @@
-use axum::response::{IntoResponse, Response, Sse, sse::Event};
+use async_stream::try_stream;
+use axum::{
+    Json,
+    body::Body,
+    http::HeaderMap,
+    response::{IntoResponse, Response, Sse, sse::Event},
+};
 use eventsource_stream::Eventsource;
-use futures::{Stream, TryStreamExt};
+use futures::Stream;
 use serde::Serialize;
+use serde_json::json;
 
 use crate::{
     claude_state::ClaudeApiFormat,
-    types::claude_message::{ContentBlockDelta, StreamEvent},
+    config::WARNING_HEADER,
+    types::claude_message::{
+        ContentBlock, ContentBlockDelta, CreateMessageResponse, StopReason, StreamEvent,
+    },
 };
 
 use super::ClaudeContext;
@@ -17,8 +27,8 @@ use super::ClaudeContext;
 /// for streaming responses. If the response is:
 ///
 /// - From the Claude API format: No transformation needed
-/// - Not streaming: No transformation needed
 /// - Has a non-200 status code: No transformation needed
+/// - OpenAI format and not streaming: Converts the message into a chat completion
 /// - OpenAI format and streaming: Transforms the stream to match OpenAI event format
 ///
 /// # Arguments
@@ -32,44 +42,192 @@ pub async fn to_oai(resp: Response) -> impl IntoResponse {
     let Some(f) = resp.extensions().get::<ClaudeContext>() else {
         return resp;
     };
-    if ClaudeApiFormat::Claude == f.api_format || !f.stream || resp.status() != 200 {
+    if ClaudeApiFormat::Claude == f.api_format || resp.status() != 200 {
         return resp;
     }
-    let body = resp.into_body();
+    if !f.stream {
+        return to_chat_completion(resp).await;
+    }
+    let f = f.to_owned();
+    let (parts, body) = resp.into_parts();
     let stream = body.into_data_stream().eventsource();
-    let stream = transform_stream(stream);
-    Sse::new(stream)
+    let stream = transform_stream(stream, f);
+    let mut resp = Sse::new(stream)
         .keep_alive(Default::default())
-        .into_response()
+        .into_response();
+    keep_warning(&parts.headers, &mut resp);
+    resp
 }
 
-/// Represents the data structure for streaming events in OpenAI API format
-/// Contains a choices array with deltas of content
+/// Copies the warning of the original response to a response built from it
+///
+/// # Arguments
+/// * `headers` - Headers of the original response
+/// * `resp` - The new response
+pub(super) fn keep_warning(headers: &HeaderMap, resp: &mut Response) {
+    if let Some(warning) = headers.get(WARNING_HEADER) {
+        resp.headers_mut()
+            .insert(WARNING_HEADER, warning.to_owned());
+    }
+}
+
+/// Non-streaming response in OpenAI API format
 #[derive(Debug, Serialize)]
-struct StreamEventData {
-    choices: Vec<StreamEventDelta>,
+struct ChatCompletion {
+    id: String,
+    object: &'static str,
+    created: i64,
+    model: String,
+    choices: Vec<ChatChoice>,
+    usage: CompletionUsage,
 }
 
-impl StreamEventData {
-    /// Creates a new StreamEventData with the given content
-    ///
-    /// # Arguments
-    /// * `content` - The event content to include
-    ///
-    /// # Returns
-    /// A new StreamEventData instance with the content wrapped in choices array
-    fn new(content: EventContent) -> Self {
-        Self {
-            choices: vec![StreamEventDelta { delta: content }],
+/// Single choice of a chat completion
+#[derive(Debug, Serialize)]
+struct ChatChoice {
+    index: usize,
+    message: ChatMessage,
+    logprobs: Option<()>,
+    finish_reason: &'static str,
+}
+
+/// Message generated by the model in OpenAI API format
+#[derive(Debug, Serialize)]
+struct ChatMessage {
+    role: &'static str,
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_content: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ChatToolCall>,
+}
+
+/// Tool call made by the model in OpenAI API format
+#[derive(Debug, Serialize)]
+struct ChatToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    type_: &'static str,
+    function: ChatFunction,
+}
+
+/// Name and arguments of a tool call, the arguments are a JSON string
+#[derive(Debug, Serialize)]
+struct ChatFunction {
+    name: String,
+    arguments: String,
+}
+
+/// Token usage in OpenAI API format
+#[derive(Debug, Serialize, Default)]
+struct CompletionUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    total_tokens: u32,
+}
+
+/// Maps a Claude stop reason to an OpenAI finish reason
+///
+/// # Arguments
+/// * `reason` - The Claude stop reason
+///
+/// # Returns
+/// The OpenAI finish reason
+fn finish_reason(reason: Option<StopReason>) -> &'static str {
+    match reason {
+        Some(StopReason::MaxTokens) => "length",
+        Some(StopReason::ToolUse) => "tool_calls",
+        _ => "stop",
+    }
+}
+
+impl From<CreateMessageResponse> for ChatCompletion {
+    fn from(message: CreateMessageResponse) -> Self {
+        let mut text = vec![];
+        let mut thinking = vec![];
+        let mut tool_calls = vec![];
+        for block in message.content {
+            match block {
+                ContentBlock::Text { text: t } => text.push(t),
+                ContentBlock::Thinking { thinking: t, .. } => thinking.push(t),
+                ContentBlock::ToolUse { id, name, input } => tool_calls.push(ChatToolCall {
+                    id,
+                    type_: "function",
+                    function: ChatFunction {
+                        name,
+                        arguments: input.to_string(),
+                    },
+                }),
+                _ => {}
+            }
+        }
+        let usage = CompletionUsage {
+            prompt_tokens: message.usage.input_tokens,
+            completion_tokens: message.usage.output_tokens,
+            total_tokens: message.usage.input_tokens + message.usage.output_tokens,
+        };
+        ChatCompletion {
+            id: format!("chatcmpl-{}", message.id),
+            object: "chat.completion",
+            created: chrono::Utc::now().timestamp(),
+            model: message.model,
+            choices: vec![ChatChoice {
+                index: 0,
+                message: ChatMessage {
+                    role: "assistant",
+                    // content is null when the model only called tools
+                    content: (!text.is_empty() || tool_calls.is_empty()).then(|| text.join("")),
+                    reasoning_content: (!thinking.is_empty()).then(|| thinking.join("")),
+                    tool_calls,
+                },
+                logprobs: None,
+                finish_reason: finish_reason(message.stop_reason),
+            }],
+            usage,
         }
     }
 }
 
+/// Converts a non-streaming Claude message into an OpenAI chat completion
+///
+/// # Arguments
+/// * `resp` - The response containing a Claude message
+///
+/// # Returns
+/// The response containing the chat completion, or unchanged if it is not a Claude message
+async fn to_chat_completion(resp: Response) -> Response {
+    let (parts, body) = resp.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let Ok(message) = serde_json::from_slice::<CreateMessageResponse>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let mut resp = Json(ChatCompletion::from(message)).into_response();
+    keep_warning(&parts.headers, &mut resp);
+    *resp.extensions_mut() = parts.extensions;
+    resp
+}
+
+/// Streaming chunk in OpenAI API format
+#[derive(Debug, Serialize)]
+struct ChatCompletionChunk<'a> {
+    id: &'a str,
+    object: &'static str,
+    created: i64,
+    model: &'a str,
+    choices: Vec<StreamEventDelta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<CompletionUsage>,
+}
+
 /// Represents a delta update in a streaming response
 /// Contains the content change for the current chunk
 #[derive(Debug, Serialize)]
 struct StreamEventDelta {
+    index: usize,
     delta: EventContent,
+    finish_reason: Option<&'static str>,
 }
 
 /// Content of an event, either regular content or reasoning (thinking mode)
@@ -77,31 +235,93 @@ struct StreamEventDelta {
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum EventContent {
+    Role { role: &'static str, content: String },
     Content { content: String },
     Reasoning { reasoning_content: String },
+    ToolCalls { tool_calls: Vec<ToolCallDelta> },
+    Empty {},
 }
 
-/// Creates an SSE event with the given content in OpenAI format
-///
-/// # Arguments
-/// * `content` - The event content to include
-///
-/// # Returns
-/// A formatted SSE Event ready to be sent to the client
-pub fn build_event(content: EventContent) -> Event {
-    let event = Event::default();
-    let data = StreamEventData::new(content);
-    event.json_data(data).unwrap()
+/// Part of a tool call in a streaming response
+#[derive(Debug, Serialize)]
+pub struct ToolCallDelta {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    type_: Option<&'static str>,
+    function: FunctionDelta,
+}
+
+/// Name and arguments of a tool call in a streaming response
+#[derive(Debug, Serialize)]
+pub struct FunctionDelta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    arguments: String,
+}
+
+/// Builds the chunks of a single streaming response, which share their metadata
+struct ChunkBuilder {
+    id: String,
+    created: i64,
+    model: String,
+}
+
+impl ChunkBuilder {
+    /// Creates an SSE event with the given content in OpenAI format
+    ///
+    /// # Arguments
+    /// * `content` - The event content to include
+    /// * `finish_reason` - The reason the response finished, for the last chunk
+    ///
+    /// # Returns
+    /// A formatted SSE Event ready to be sent to the client
+    fn event(&self, content: EventContent, finish_reason: Option<&'static str>) -> Event {
+        self.build(
+            vec![StreamEventDelta {
+                index: 0,
+                delta: content,
+                finish_reason,
+            }],
+            None,
+        )
+    }
+
+    /// Creates the usage chunk sent at the end of the stream, which has no choices
+    ///
+    /// # Arguments
+    /// * `usage` - Token usage of the response
+    ///
+    /// # Returns
+    /// A formatted SSE Event ready to be sent to the client
+    fn usage_event(&self, usage: CompletionUsage) -> Event {
+        self.build(vec![], Some(usage))
+    }
+
+    fn build(&self, choices: Vec<StreamEventDelta>, usage: Option<CompletionUsage>) -> Event {
+        let chunk = ChatCompletionChunk {
+            id: &self.id,
+            object: "chat.completion.chunk",
+            created: self.created,
+            model: &self.model,
+            choices,
+            usage,
+        };
+        Event::default().json_data(chunk).unwrap()
+    }
 }
 
 /// Transforms a Claude.ai event stream into an OpenAI-compatible event stream
 ///
 /// Extracts content from Claude events and reformats them to match OpenAI's streaming format.
-/// This function processes each event in the stream, identifying the delta content type
-/// (text or thinking), and converting it to the appropriate OpenAI-compatible event format.
+/// The first chunk carries the role, content deltas follow as text, reasoning or tool call
+/// chunks, and the last chunk carries the finish reason. A usage chunk is sent if the client
+/// asked for it, and the stream is terminated with `[DONE]`.
 ///
 /// # Arguments
 /// * `s` - The input stream of Claude.ai events
+/// * `f` - Context of the request
 ///
 /// # Returns
 /// A stream of OpenAI-compatible SSE events
@@ -109,28 +329,124 @@ pub fn build_event(content: EventContent) -> Event {
 /// # Type Parameters
 /// * `I` - The input stream type
 /// * `E` - The error type for the stream
-pub fn transform_stream<I, E>(s: I) -> impl Stream<Item = Result<Event, E>> + Send
+pub fn transform_stream<I, E>(s: I, f: ClaudeContext) -> impl Stream<Item = Result<Event, E>> + Send
 where
     I: Stream<Item = Result<eventsource_stream::Event, E>> + Send,
     E: Send,
 {
-    s.try_filter_map(async |eventsource_stream::Event { data, .. }| {
-        let Ok(parsed) = serde_json::from_str::<StreamEvent>(&data) else {
-            return Ok(None);
+    try_stream! {
+        let mut chunk = ChunkBuilder {
+            id: format!("chatcmpl-{}", uuid::Uuid::new_v4().simple()),
+            created: chrono::Utc::now().timestamp(),
+            model: f.model,
         };
-        let StreamEvent::ContentBlockDelta { delta, .. } = parsed else {
-            return Ok(None);
-        };
-        match delta {
-            ContentBlockDelta::TextDelta { text } => {
-                Ok(Some(build_event(EventContent::Content { content: text })))
+        let mut usage = CompletionUsage::default();
+        let mut started = false;
+        let mut finished = false;
+        // number of tool calls started so far
+        let mut tool_calls = 0;
+        for await event in s {
+            let eventsource_stream::Event { data, .. } = event?;
+            let Ok(parsed) = serde_json::from_str::<StreamEvent>(&data) else {
+                continue;
+            };
+            if let StreamEvent::MessageStart { message } = &parsed {
+                if !message.id.is_empty() && !started {
+                    chunk.id = format!("chatcmpl-{}", message.id);
+                }
+                usage.prompt_tokens = message.usage.input_tokens;
+            }
+            if !started {
+                started = true;
+                yield chunk.event(
+                    EventContent::Role {
+                        role: "assistant",
+                        content: String::new(),
+                    },
+                    None,
+                );
             }
-            ContentBlockDelta::ThinkingDelta { thinking } => {
-                Ok(Some(build_event(EventContent::Reasoning {
-                    reasoning_content: thinking,
-                })))
+            match parsed {
+                StreamEvent::ContentBlockStart {
+                    content_block: ContentBlock::ToolUse { id, name, .. },
+                    ..
+                } => {
+                    tool_calls += 1;
+                    yield chunk.event(
+                        EventContent::ToolCalls {
+                            tool_calls: vec![ToolCallDelta {
+                                index: tool_calls - 1,
+                                id: Some(id),
+                                type_: Some("function"),
+                                function: FunctionDelta {
+                                    name: Some(name),
+                                    arguments: String::new(),
+                                },
+                            }],
+                        },
+                        None,
+                    );
+                }
+                StreamEvent::ContentBlockDelta { delta, .. } => match delta {
+                    ContentBlockDelta::TextDelta { text } => {
+                        yield chunk.event(EventContent::Content { content: text }, None);
+                    }
+                    ContentBlockDelta::ThinkingDelta { thinking } => {
+                        yield chunk.event(
+                            EventContent::Reasoning {
+                                reasoning_content: thinking,
+                            },
+                            None,
+                        );
+                    }
+                    ContentBlockDelta::InputJsonDelta { partial_json } if tool_calls > 0 => {
+                        yield chunk.event(
+                            EventContent::ToolCalls {
+                                tool_calls: vec![ToolCallDelta {
+                                    index: tool_calls - 1,
+                                    id: None,
+                                    type_: None,
+                                    function: FunctionDelta {
+                                        name: None,
+                                        arguments: partial_json,
+                                    },
+                                }],
+                            },
+                            None,
+                        );
+                    }
+                    _ => {}
+                },
+                // the upstream failed, there is no finish reason
+                StreamEvent::Error { error } if !finished => {
+                    finished = true;
+                    yield Event::default()
+                        .json_data(json!({
+                            "error": { "message": error.message, "type": error.type_ }
+                        }))
+                        .unwrap();
+                }
+                StreamEvent::MessageDelta { delta, usage: u } if !finished => {
+                    finished = true;
+                    if let Some(u) = u {
+                        usage.completion_tokens = u.output_tokens;
+                        if u.input_tokens > 0 {
+                            usage.prompt_tokens = u.input_tokens;
+                        }
+                    }
+                    let reason = finish_reason(delta.stop_reason);
+                    yield chunk.event(EventContent::Empty {}, Some(reason));
+                }
+                _ => {}
             }
-            _ => Ok(None),
         }
-    })
+        if !finished {
+            yield chunk.event(EventContent::Empty {}, Some(finish_reason(None)));
+        }
+        if f.include_usage {
+            usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+            yield chunk.usage_event(usage);
+        }
+        yield Event::default().data("[DONE]");
+    }
 }
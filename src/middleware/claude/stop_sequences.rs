@@ -1,13 +1,21 @@
 use async_stream::try_stream;
-use axum::response::{IntoResponse, Response, Sse, sse::Event};
+use axum::{
+    Json,
+    body::Body,
+    response::{IntoResponse, Response, Sse, sse::Event},
+};
 use eventsource_stream::{Event as SourceEvent, Eventsource};
 use futures::Stream;
 
-use crate::types::claude_message::{
-    ContentBlockDelta, MessageDeltaContent, StopReason, StreamEvent,
+use crate::{
+    types::claude_message::{
+        ContentBlock, ContentBlockDelta, CreateMessageResponse, MessageDeltaContent, StopReason,
+        StreamEvent, StreamUsage,
+    },
+    utils::count_tokens,
 };
 
-use super::ClaudeContext;
+use super::{ClaudeContext, response::keep_warning};
 
 type EventResult<T> = Result<T, eventsource_stream::EventStreamError<axum::Error>>;
 
@@ -18,6 +26,9 @@ fn stop_stream(
     let trie = trie_rs::map::Trie::from_iter(sequences.into_iter().map(|s| (s.to_owned(), s)));
     try_stream!({
         let mut searches = vec![trie.inc_search()];
+        // usage of the truncated message
+        let mut input_tokens = 0;
+        let mut output = String::new();
         for await event in stream {
             let eventsource_stream::Event {
                 data,
@@ -36,12 +47,23 @@ fn stop_stream(
                 continue;
             };
             let StreamEvent::ContentBlockDelta { delta, index } = parsed else {
+                if let StreamEvent::MessageStart { message } = parsed {
+                    input_tokens = message.usage.input_tokens;
+                }
                 yield event;
                 continue;
             };
-            let ContentBlockDelta::TextDelta { text } = delta else {
-                yield event;
-                continue;
+            let text = match delta {
+                ContentBlockDelta::TextDelta { text } => text,
+                ContentBlockDelta::ThinkingDelta { thinking } => {
+                    output.push_str(&thinking);
+                    yield event;
+                    continue;
+                }
+                _ => {
+                    yield event;
+                    continue;
+                }
             };
             let input = text.into_bytes();
             for i in 0..input.len() {
@@ -53,6 +75,7 @@ fn stop_stream(
                             let seq = s.value().unwrap();
                             // stop sequence found
                             let result = String::from_utf8_lossy(&input[..i + 1]).to_string();
+                            output.push_str(&result);
                             let event = StreamEvent::ContentBlockDelta {
                                 delta: ContentBlockDelta::TextDelta { text: result },
                                 index,
@@ -63,7 +86,10 @@ fn stop_stream(
                                     stop_reason: Some(StopReason::StopSequence),
                                     stop_sequence: Some(seq.to_string()),
                                 },
-                                usage: None,
+                                usage: Some(StreamUsage {
+                                    input_tokens,
+                                    output_tokens: count_tokens(&output) as u32,
+                                }),
                             };
                             let message_stop = StreamEvent::MessageStop;
 
@@ -81,24 +107,71 @@ fn stop_stream(
                 }
                 searches = next_searches;
             }
+            output.push_str(&String::from_utf8_lossy(&input));
             yield event;
         }
     })
 }
 
+/// Truncates a non-stream message at the first stop sequence found in its text
+///
+/// # Arguments
+/// * `sequences` - The stop sequences of the request
+/// * `resp` - The non-stream response
+///
+/// # Returns
+/// The response with the message truncated, or unchanged if no stop sequence was found
+async fn stop_message(sequences: &[String], resp: Response) -> Response {
+    let (parts, body) = resp.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    let Ok(mut message) = serde_json::from_slice::<CreateMessageResponse>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    let found = message.content.iter().enumerate().find_map(|(i, block)| {
+        let ContentBlock::Text { text } = block else {
+            return None;
+        };
+        sequences
+            .iter()
+            .filter_map(|seq| text.find(seq.as_str()).map(|pos| (i, pos, seq)))
+            .min_by_key(|(_, pos, _)| *pos)
+    });
+    let Some((i, pos, seq)) = found else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    if let ContentBlock::Text { text } = &mut message.content[i] {
+        text.truncate(pos);
+    }
+    message.content.truncate(i + 1);
+    message.stop_reason = Some(StopReason::StopSequence);
+    message.stop_sequence = Some(seq.to_owned());
+    message.usage.output_tokens = message.count_output_tokens();
+    let mut resp = Json(message).into_response();
+    keep_warning(&parts.headers, &mut resp);
+    *resp.extensions_mut() = parts.extensions;
+    resp
+}
+
 pub async fn apply_stop_sequences(resp: Response) -> Response {
     let Some(f) = resp.extensions().get::<ClaudeContext>().cloned() else {
         return resp;
     };
-    if !f.stream || resp.status() != 200 || f.stop_sequences.is_empty() {
+    if resp.status() != 200 || f.stop_sequences.is_empty() {
         return resp;
     }
+    if !f.stream {
+        return stop_message(&f.stop_sequences, resp).await;
+    }
 
-    let stream = resp.into_body().into_data_stream().eventsource();
+    let (parts, body) = resp.into_parts();
+    let stream = body.into_data_stream().eventsource();
     let stream = stop_stream(f.stop_sequences.to_owned(), stream);
     let mut resp = Sse::new(stream)
         .keep_alive(Default::default())
         .into_response();
+    keep_warning(&parts.headers, &mut resp);
 
     resp.extensions_mut().insert(f);
     resp
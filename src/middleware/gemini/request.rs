@@ -28,11 +28,6 @@ impl FromRequest<GeminiState> for GeminiPreprocess {
     async fn from_request(mut req: Request, state: &GeminiState) -> Result<Self, Self::Rejection> {
         let Path(path) = req.extract_parts::<Path<String>>().await?;
         let vertex = req.uri().to_string().contains("vertex");
-        if vertex && !CLEWDR_CONFIG.load().vertex.validate() {
-            return Err(ClewdrError::BadRequest {
-                msg: "Vertex is not configured",
-            });
-        }
         let mut model = path
             .split('/')
             .next_back()
@@ -72,11 +67,6 @@ impl FromRequest<GeminiState> for GeminiOaiPreprocess {
 
     async fn from_request(req: Request, state: &GeminiState) -> Result<Self, Self::Rejection> {
         let vertex = req.uri().to_string().contains("vertex");
-        if vertex && !CLEWDR_CONFIG.load().vertex.validate() {
-            return Err(ClewdrError::BadRequest {
-                msg: "Vertex is not configured",
-            });
-        }
         let Json(mut body) = Json::<CreateMessageParams>::from_request(req, &()).await?;
         let model = body.model.to_owned();
         if vertex {
@@ -10,4 +10,6 @@ mod auth;
 pub mod claude;
 pub mod gemini;
 
-pub use auth::{RequireAdminAuth, RequireBearerAuth, RequireQueryKeyAuth, RequireXApiKeyAuth};
+pub use auth::{
+    KeyGroups, RequireAdminAuth, RequireBearerAuth, RequireQueryKeyAuth, RequireXApiKeyAuth,
+};
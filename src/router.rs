@@ -11,11 +11,15 @@ use tower_http::{compression::CompressionLayer, cors::CorsLayer, services::Serve
 use crate::{
     IS_DEBUG,
     api::{
-        api_auth, api_claude, api_delete_cookie, api_delete_key, api_get_config, api_get_cookies,
-        api_get_keys, api_get_models, api_post_config, api_post_cookie, api_post_gemini,
-        api_post_gemini_oai, api_post_key, api_version,
+        api_auth, api_check_cookies, api_claude, api_delete_cookie, api_delete_key,
+        api_delete_vertex, api_export_cookies, api_get_config, api_get_cookies, api_get_keys,
+        api_get_models, api_get_vertex, api_post_config, api_post_cookie, api_post_cookies_bulk,
+        api_post_gemini, api_post_gemini_oai, api_post_key, api_post_vertex,
+        api_revalidate_cookies, api_set_cookie_groups, api_set_cookie_weight, api_test_cookie,
+        api_test_key, api_version,
     },
     claude_state::ClaudeState,
+    config::PoolState,
     gemini_state::GeminiState,
     middleware::{
         RequireAdminAuth, RequireBearerAuth, RequireQueryKeyAuth, RequireXApiKeyAuth,
@@ -23,7 +27,10 @@ use crate::{
     },
     services::{
         cookie_manager::{CookieEventSender, CookieManager},
+        health_check::CookieHealthChecker,
         key_manager::{KeyEventSender, KeyManager},
+        state_writer::StateWriter,
+        vertex_manager::{VertexEventSender, VertexManager},
     },
 };
 
@@ -31,8 +38,11 @@ use crate::{
 pub struct RouterBuilder {
     claude_state: ClaudeState,
     cookie_event_sender: CookieEventSender,
+    health_checker: CookieHealthChecker,
     key_event_sender: KeyEventSender,
+    vertex_event_sender: VertexEventSender,
     gemini_state: GeminiState,
+    writer: StateWriter,
     inner: Router,
 }
 
@@ -49,15 +59,22 @@ impl RouterBuilder {
     /// # Arguments
     /// * `state` - The application state containing client information
     pub fn new() -> Self {
-        let cookie_tx = CookieManager::start();
+        let state = PoolState::load();
+        let writer = StateWriter::start(state.to_owned());
+        let cookie_tx = CookieManager::start(&state, writer.to_owned());
         let claude_state = ClaudeState::new(cookie_tx.to_owned());
-        let key_tx = KeyManager::start();
-        let gemini_state = GeminiState::new(key_tx.to_owned());
+        let health_checker = CookieHealthChecker::start(claude_state.to_owned());
+        let key_tx = KeyManager::start(&state, writer.to_owned());
+        let vertex_tx = VertexManager::start(&state, writer.to_owned());
+        let gemini_state = GeminiState::new(key_tx.to_owned(), vertex_tx.to_owned());
         RouterBuilder {
             claude_state,
             cookie_event_sender: cookie_tx,
+            health_checker,
             key_event_sender: key_tx,
+            vertex_event_sender: vertex_tx,
             gemini_state,
+            writer,
             inner: Router::new(),
         }
     }
@@ -111,12 +128,30 @@ impl RouterBuilder {
     fn route_api_endpoints(mut self) -> Self {
         let cookie_router = Router::new()
             .route("/cookies", get(api_get_cookies))
+            .route("/cookies/bulk", post(api_post_cookies_bulk))
+            .route("/cookies/export", get(api_export_cookies))
+            .route("/cookie/groups", post(api_set_cookie_groups))
+            .route("/cookie/weight", post(api_set_cookie_weight))
             .route("/cookie", delete(api_delete_cookie).post(api_post_cookie))
+            .route("/cookie/test", post(api_test_cookie))
             .with_state(self.cookie_event_sender.to_owned());
+        let health_router = Router::new()
+            .route("/cookies/check", post(api_check_cookies))
+            .route("/cookies/revalidate", post(api_revalidate_cookies))
+            .with_state(self.health_checker.to_owned());
         let key_router = Router::new()
             .route("/key", post(api_post_key).delete(api_delete_key))
+            .route("/key/test", post(api_test_key))
             .route("/keys", get(api_get_keys))
             .with_state(self.key_event_sender.to_owned());
+        let vertex_router = Router::new()
+            .route(
+                "/vertex",
+                get(api_get_vertex)
+                    .post(api_post_vertex)
+                    .delete(api_delete_vertex),
+            )
+            .with_state(self.vertex_event_sender.to_owned());
         let admin_router = Router::new()
             .route("/auth", get(api_auth))
             .route("/config", get(api_get_config).put(api_post_config));
@@ -124,7 +159,9 @@ impl RouterBuilder {
             .nest(
                 "/api",
                 cookie_router
+                    .merge(health_router)
                     .merge(key_router)
+                    .merge(vertex_router)
                     .merge(admin_router)
                     .layer(from_extractor::<RequireAdminAuth>()),
             )
@@ -190,6 +227,11 @@ impl RouterBuilder {
         self
     }
 
+    /// Returns the writer of the pool state, to flush it on shutdown
+    pub fn state_writer(&self) -> StateWriter {
+        self.writer.to_owned()
+    }
+
     /// Returns the configured router
     /// Finalizes the router configuration for use with axum
     pub fn build(self) -> Router {
@@ -14,7 +14,7 @@ use crate::{
     config::CLEWDR_CONFIG,
     error::{ClewdrError, RquestSnafu},
     types::{
-        claude_message::{CreateMessageParams, Message, Role},
+        claude_message::{CreateMessageParams, Message, Role, Tool as ClaudeTool, ToolChoice},
         gemini::request::{Chat, GeminiRequestBody, SystemInstruction, Tool},
     },
 };
@@ -220,6 +220,10 @@ struct ClaudeRequestKeys<'a> {
     pub thinking: bool,
     /// Top-k sampling
     pub top_k: Option<u32>,
+    /// Tools that the model may use
+    pub tools: Option<&'a Vec<ClaudeTool>>,
+    /// How the model should use tools
+    pub tool_choice: Option<&'a ToolChoice>,
 }
 
 #[derive(Hash, Debug)]
@@ -344,8 +348,10 @@ impl<'a> From<&'a CreateMessageParams> for ClaudeRequestKeys<'a> {
             model: params.model.to_owned(),
             system: params.system.as_ref(),
             stop_sequences: params.stop_sequences.to_owned(),
-            thinking: params.thinking.is_some(),
+            thinking: params.thinking_enabled(),
             top_k: params.top_k,
+            tools: params.tools.as_ref(),
+            tool_choice: params.tool_choice.as_ref(),
         }
     }
 }
@@ -0,0 +1,248 @@
+use axum::http::HeaderMap;
+use moka::{notification::RemovalCause, sync::Cache};
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::LazyLock,
+    time::Duration,
+};
+use tokio::runtime::Handle;
+use tracing::warn;
+
+use crate::{
+    claude_state::ClaudeState,
+    config::{CLEWDR_CONFIG, ClewdrCookie, CookieStatus},
+    services::cookie_manager::CookieEventSender,
+    types::claude_message::{ContentBlock, CreateMessageParams, Message, MessageContent, Role},
+};
+
+/// Header identifying the conversation of a request, instead of its fingerprint
+pub const CONVERSATION_HEADER: &str = "x-conversation-id";
+
+/// Global store of the Claude.ai conversations kept for client conversations
+pub static CONVERSATIONS: LazyLock<ConversationStore> = LazyLock::new(ConversationStore::default);
+
+/// A Claude.ai conversation mirroring a client conversation
+#[derive(Clone)]
+pub struct Conversation {
+    /// Cookie owning the conversation
+    pub cookie: ClewdrCookie,
+    /// Sender used to delete the conversation with its cookie
+    event_sender: CookieEventSender,
+    /// Organization of the conversation
+    pub org_uuid: String,
+    /// UUID of the conversation on Claude.ai
+    pub conv_uuid: String,
+    /// Hash of the system prompt
+    system: u64,
+    /// Hashes of the client messages already sent to Claude.ai
+    history: Vec<u64>,
+    /// Text generated for the last turn, None until the response is complete
+    reply: Option<String>,
+}
+
+impl Conversation {
+    /// Creates the record of a conversation that received the messages of a request
+    ///
+    /// # Arguments
+    /// * `cookie` - Cookie owning the conversation
+    /// * `event_sender` - Sender used to delete the conversation
+    /// * `org_uuid` - Organization of the conversation
+    /// * `conv_uuid` - UUID of the conversation on Claude.ai
+    /// * `p` - The request sent to the conversation
+    ///
+    /// # Returns
+    /// The conversation, waiting for the reply to be recorded
+    pub fn new(
+        cookie: ClewdrCookie,
+        event_sender: CookieEventSender,
+        org_uuid: String,
+        conv_uuid: String,
+        p: &CreateMessageParams,
+    ) -> Self {
+        Self {
+            cookie,
+            event_sender,
+            org_uuid,
+            conv_uuid,
+            system: hash(&p.system),
+            history: p.messages.iter().map(hash).collect(),
+            reply: None,
+        }
+    }
+
+    /// Deletes the conversation on Claude.ai, with the cookie owning it
+    async fn delete(self) {
+        let mut state = ClaudeState::new(self.event_sender);
+        let cookie = CookieStatus {
+            cookie: self.cookie,
+            ..Default::default()
+        };
+        if let Err(e) = state.set_cookie(cookie) {
+            warn!("Failed to delete chat {}: {}", self.conv_uuid, e);
+            return;
+        }
+        state.delete_chat(&self.org_uuid, &self.conv_uuid).await;
+    }
+
+    /// Finds the messages of a request that continue the conversation
+    ///
+    /// The request must repeat the history already sent, followed by the reply
+    /// generated for it and by new user messages. The reply is matched as a prefix
+    /// of the generated text, as stop sequences may have cut what the client received.
+    /// Edited or regenerated turns do not match, so the conversation has to be rebuilt.
+    ///
+    /// # Arguments
+    /// * `p` - The new request of the client
+    ///
+    /// # Returns
+    /// * `Option<&[Message]>` - The new user messages, None if the history diverged
+    pub fn new_turn<'a>(&self, p: &'a CreateMessageParams) -> Option<&'a [Message]> {
+        let reply = self.reply.as_deref()?;
+        let n = self.history.len();
+        if self.system != hash(&p.system) || p.messages.len() < n + 2 {
+            return None;
+        }
+        if !p.messages[..n]
+            .iter()
+            .map(hash)
+            .eq(self.history.iter().copied())
+        {
+            return None;
+        }
+        let answer = &p.messages[n];
+        let sent = message_text(answer);
+        let sent = sent.trim();
+        // an empty answer only matches an empty reply
+        if answer.role != Role::Assistant
+            || !reply.starts_with(sent)
+            || (sent.is_empty() && !reply.is_empty())
+        {
+            return None;
+        }
+        let turn = &p.messages[n + 1..];
+        turn.iter().all(|m| m.role == Role::User).then_some(turn)
+    }
+}
+
+/// Store of the Claude.ai conversations, keyed by conversation fingerprint
+///
+/// Conversations are forgotten after an hour of inactivity. The store owns the
+/// chats on Claude.ai: a conversation removed or evicted from it is deleted,
+/// unless `preserve_chats` is set.
+pub struct ConversationStore {
+    moka: Cache<u64, Conversation>,
+}
+
+impl Default for ConversationStore {
+    fn default() -> Self {
+        Self {
+            moka: Cache::builder()
+                .max_capacity(1000)
+                .time_to_idle(Duration::from_secs(60 * 60))
+                .eviction_listener(|_, conversation: Conversation, cause| {
+                    // the next turn of a conversation replaces it in the same chat
+                    if cause == RemovalCause::Replaced || CLEWDR_CONFIG.load().preserve_chats {
+                        return;
+                    }
+                    match Handle::try_current() {
+                        Ok(handle) => {
+                            handle.spawn(conversation.delete());
+                        }
+                        Err(_) => warn!("Failed to delete chat {}", conversation.conv_uuid),
+                    }
+                })
+                .build(),
+        }
+    }
+}
+
+impl ConversationStore {
+    /// Gets the conversation kept for a fingerprint
+    pub fn get(&self, key: u64) -> Option<Conversation> {
+        self.moka.get(&key)
+    }
+
+    /// Keeps a conversation for a fingerprint
+    ///
+    /// A previous conversation in another chat is removed, which deletes its chat.
+    pub fn insert(&self, key: u64, conversation: Conversation) {
+        if self
+            .moka
+            .get(&key)
+            .is_some_and(|c| c.conv_uuid != conversation.conv_uuid)
+        {
+            self.moka.invalidate(&key);
+        }
+        self.moka.insert(key, conversation);
+    }
+
+    /// Records the text generated for the last turn of a conversation
+    ///
+    /// Nothing is recorded if the conversation was removed or replaced meanwhile.
+    ///
+    /// # Arguments
+    /// * `key` - Fingerprint of the conversation
+    /// * `conv_uuid` - UUID of the chat that generated the text
+    /// * `text` - The generated text
+    pub fn set_reply(&self, key: u64, conv_uuid: &str, text: &str) {
+        let Some(mut conversation) = self.get_chat(key, conv_uuid) else {
+            return;
+        };
+        conversation.reply = Some(text.trim().to_string());
+        self.moka.insert(key, conversation);
+    }
+
+    /// Forgets the conversation kept for a fingerprint, deleting its chat
+    pub fn remove(&self, key: u64) {
+        self.moka.invalidate(&key);
+    }
+
+    /// Forgets the conversation kept for a fingerprint if it is in the given chat
+    ///
+    /// # Returns
+    /// * `bool` - Whether the chat was kept, it is then deleted by the store
+    pub fn remove_chat(&self, key: u64, conv_uuid: &str) -> bool {
+        let kept = self.get_chat(key, conv_uuid).is_some();
+        if kept {
+            self.moka.invalidate(&key);
+        }
+        kept
+    }
+
+    fn get_chat(&self, key: u64, conv_uuid: &str) -> Option<Conversation> {
+        self.moka.get(&key).filter(|c| c.conv_uuid == conv_uuid)
+    }
+}
+
+/// Gets the conversation fingerprint from the conversation header of a request
+///
+/// # Arguments
+/// * `headers` - Headers of the request
+///
+/// # Returns
+/// * `Option<u64>` - The fingerprint, None if the header is missing or empty
+pub fn header_fingerprint(headers: &HeaderMap) -> Option<u64> {
+    let id = headers.get(CONVERSATION_HEADER)?.to_str().ok()?.trim();
+    (!id.is_empty()).then(|| hash(id))
+}
+
+fn hash(value: &(impl Hash + ?Sized)) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Extracts the text of a message, as generated by the model
+fn message_text(msg: &Message) -> String {
+    match &msg.content {
+        MessageContent::Text { content } => content.to_owned(),
+        MessageContent::Blocks { content } => content
+            .iter()
+            .filter_map(|b| match b {
+                ContentBlock::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(""),
+    }
+}
@@ -1,16 +1,28 @@
 use colored::Colorize;
+use rand::{Rng, rng};
 use serde::Serialize;
-use std::collections::{HashSet, VecDeque};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+};
+use strum::Display;
 use tokio::{
     spawn,
     sync::{mpsc, oneshot},
-    time::Interval,
+    time::{Duration, Instant, Interval, timeout_at},
 };
 use tracing::{error, info, warn};
 
 use crate::{
-    config::{CLEWDR_CONFIG, ClewdrConfig, CookieStatus, Reason, UselessCookie},
+    config::{
+        CLEWDR_CONFIG, ClewdrCookie, CookieStatus, CookieUsage, DispatchStrategy, PlanTier,
+        PoolState, Reason, UselessCookie,
+    },
     error::ClewdrError,
+    services::{
+        state_writer::StateWriter,
+        webhook::{WEBHOOK, WebhookEvent},
+    },
 };
 
 const INTERVAL: u64 = 300;
@@ -22,27 +34,115 @@ pub struct CookieStatusInfo {
     pub invalid: Vec<UselessCookie>,
 }
 
+/// Outcome of importing a single cookie
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ImportStatus {
+    /// The cookie was added to the valid collection
+    Accepted,
+    /// The cookie is already valid or exhausted
+    Duplicate,
+    /// The cookie does not have a valid format
+    Malformed,
+    /// The cookie was already found to be unusable
+    Wasted,
+    /// The cookie failed the probe on submit and was not imported
+    Rejected,
+}
+
 /// Unified event enum for cookie management with built-in priority ordering
 #[derive(Debug)]
 pub enum CookieEvent {
-    /// Return a Cookie
-    Return(CookieStatus, Option<Reason>),
+    /// Return a Cookie with the outcome of the request it served
+    Return(CookieStatus, Option<Reason>, Option<CookieUsage>),
+    /// Release the lease of a Cookie that is no longer in use
+    Release(u64),
     /// Submit a new Cookie
     Submit(CookieStatus),
+    /// Import a batch of Cookies and report the outcome of each
+    Import(Vec<CookieStatus>, oneshot::Sender<Vec<ImportStatus>>),
     /// Check for timed out Cookies
     CheckReset,
+    /// Report the result of bootstrapping an invalid Cookie again
+    Revalidated(CookieStatus, Option<Reason>),
     /// Request to get a Cookie
-    Request(oneshot::Sender<Result<CookieStatus, ClewdrError>>),
+    Request(CookieRequest, LeaseSender),
+    /// Lease a specific Cookie for a check, if it is below its concurrency limit
+    Lease(ClewdrCookie, oneshot::Sender<Option<CookieLease>>),
+    /// Set the groups of a Cookie
+    SetGroups(
+        ClewdrCookie,
+        Vec<String>,
+        oneshot::Sender<Result<(), ClewdrError>>,
+    ),
+    /// Set the dispatch weight of a Cookie
+    SetWeight(ClewdrCookie, u32, oneshot::Sender<Result<(), ClewdrError>>),
     /// Get all Cookie status information
     GetStatus(oneshot::Sender<CookieStatusInfo>),
     /// Delete a Cookie
     Delete(CookieStatus, oneshot::Sender<Result<(), ClewdrError>>),
 }
+
+/// Constraints on the cookie handed out for a request
+#[derive(Debug, Clone, Default)]
+pub struct CookieRequest {
+    /// Conversation fingerprint, requests with the same fingerprint
+    /// prefer the cookie that served the conversation before
+    pub fingerprint: Option<u64>,
+    /// Groups the caller is restricted to, None for every cookie
+    pub groups: Option<Vec<String>>,
+    /// Cookies that already failed the request, never handed out again
+    pub exclude: Vec<ClewdrCookie>,
+}
+
+impl CookieRequest {
+    /// Checks if a cookie may serve the request, regardless of its load
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to check
+    ///
+    /// # Returns
+    /// * `bool` - Whether the cookie is in the groups and not excluded
+    fn allows(&self, cookie: &CookieStatus) -> bool {
+        cookie.in_groups(self.groups.as_deref()) && !self.exclude.contains(&cookie.cookie)
+    }
+}
+
+/// A cookie handed out by the cookie manager
+///
+/// The cookie counts as in flight until the lease is dropped,
+/// which releases it even if the request panics or is cancelled
+#[derive(Debug)]
+pub struct CookieLease {
+    id: u64,
+    pub cookie: CookieStatus,
+    sender: mpsc::UnboundedSender<CookieEvent>,
+}
+
+impl Drop for CookieLease {
+    fn drop(&mut self) {
+        // the manager may already be gone during shutdown
+        let _ = self.sender.send(CookieEvent::Release(self.id));
+    }
+}
+
+/// Sender used to answer a cookie request
+type LeaseSender = oneshot::Sender<Result<CookieLease, ClewdrError>>;
+
 /// Cookie manager that handles cookie distribution, collection, and status tracking
 pub struct CookieManager {
     valid: VecDeque<CookieStatus>,
     exhausted: HashSet<CookieStatus>,
     invalid: HashSet<UselessCookie>,
+    leases: HashMap<u64, ClewdrCookie>, // Cookies of the leases currently held
+    in_flight: HashMap<ClewdrCookie, usize>, // Number of leases held for each cookie
+    next_lease: u64,                    // Id of the next lease
+    waiting: VecDeque<(Instant, CookieRequest, LeaseSender)>, // Requests waiting for a free cookie and their arrival
+    affinity: HashMap<u64, (ClewdrCookie, i64)>, // Cookie pinned to each conversation and its expiry
+    dirty: bool,                                 // Statistics changed since the last save
+    writer: StateWriter,                         // Writer of the pool state file
+    event_tx: mpsc::UnboundedSender<CookieEvent>, // Event sender used to release leases
     event_rx: mpsc::UnboundedReceiver<CookieEvent>, // Event receiver for incoming events
 }
 
@@ -54,20 +154,41 @@ pub struct CookieEventSender {
 
 impl CookieEventSender {
     /// Request a cookie from the cookie manager
+    /// Waits for a cookie to be released if all valid cookies are at their concurrency limit,
+    /// up to the configured cookie wait timeout
+    ///
+    /// # Arguments
+    /// * `request` - Constraints on the cookie to hand out
     ///
     /// # Returns
-    /// * `Result<CookieStatus, ClewdrError>` - Cookie if available, error otherwise
-    pub async fn request(&self) -> Result<CookieStatus, ClewdrError> {
+    /// * `Result<CookieLease, ClewdrError>` - Lease of a cookie if available, error otherwise
+    pub async fn request(&self, request: CookieRequest) -> Result<CookieLease, ClewdrError> {
         let (tx, rx) = oneshot::channel();
-        self.sender.send(CookieEvent::Request(tx))?;
+        self.sender.send(CookieEvent::Request(request, tx))?;
         rx.await?
     }
 
+    /// Lease a specific valid or exhausted cookie, e.g. to check it in the background
+    /// Does not wait, and does not count as a dispatch in the cookie statistics
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to lease
+    ///
+    /// # Returns
+    /// * `Result<Option<CookieLease>, ClewdrError>` - Lease of the cookie,
+    ///   None if it is at its concurrency limit or no longer in the pool
+    pub async fn lease(&self, cookie: ClewdrCookie) -> Result<Option<CookieLease>, ClewdrError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(CookieEvent::Lease(cookie, tx))?;
+        Ok(rx.await?)
+    }
+
     /// Return a cookie to the cookie manager with optional reason
     ///
     /// # Arguments
     /// * `cookie` - The cookie to return
     /// * `reason` - Optional reason for returning the cookie (e.g., invalid, restricted)
+    /// * `usage` - Optional outcome of the request, recorded in the cookie statistics
     ///
     /// # Returns
     /// Result indicating success or send error
@@ -75,8 +196,9 @@ impl CookieEventSender {
         &self,
         cookie: CookieStatus,
         reason: Option<Reason>,
+        usage: Option<CookieUsage>,
     ) -> Result<(), mpsc::error::SendError<CookieEvent>> {
-        self.sender.send(CookieEvent::Return(cookie, reason))
+        self.sender.send(CookieEvent::Return(cookie, reason, usage))
     }
 
     /// Submit a new cookie to the cookie manager
@@ -93,6 +215,23 @@ impl CookieEventSender {
         self.sender.send(CookieEvent::Submit(cookie))
     }
 
+    /// Import a batch of cookies into the cookie manager
+    /// The configuration is saved once for the whole batch
+    ///
+    /// # Arguments
+    /// * `cookies` - The cookies to import
+    ///
+    /// # Returns
+    /// * `Result<Vec<ImportStatus>, ClewdrError>` - Outcome of each cookie, in order
+    pub async fn import(
+        &self,
+        cookies: Vec<CookieStatus>,
+    ) -> Result<Vec<ImportStatus>, ClewdrError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(CookieEvent::Import(cookies, tx))?;
+        Ok(rx.await?)
+    }
+
     /// Get status information about all cookies
     ///
     /// # Returns
@@ -116,6 +255,56 @@ impl CookieEventSender {
         rx.await?
     }
 
+    /// Report the result of bootstrapping an invalid cookie again
+    ///
+    /// # Arguments
+    /// * `cookie` - The bootstrapped cookie
+    /// * `reason` - Reason the cookie is still unusable, None if it passed
+    ///
+    /// # Returns
+    /// Result indicating success or send error
+    pub async fn revalidated(
+        &self,
+        cookie: CookieStatus,
+        reason: Option<Reason>,
+    ) -> Result<(), mpsc::error::SendError<CookieEvent>> {
+        self.sender.send(CookieEvent::Revalidated(cookie, reason))
+    }
+
+    /// Set the groups of a cookie
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to update
+    /// * `groups` - The new groups of the cookie
+    ///
+    /// # Returns
+    /// * `Result<(), ClewdrError>` - Success or error if the cookie is not valid or exhausted
+    pub async fn set_groups(
+        &self,
+        cookie: ClewdrCookie,
+        groups: Vec<String>,
+    ) -> Result<(), ClewdrError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(CookieEvent::SetGroups(cookie, groups, tx))?;
+        rx.await?
+    }
+
+    /// Set the dispatch weight of a cookie
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to update
+    /// * `weight` - The new weight of the cookie
+    ///
+    /// # Returns
+    /// * `Result<(), ClewdrError>` - Success or error if the cookie is not valid or exhausted
+    pub async fn set_weight(&self, cookie: ClewdrCookie, weight: u32) -> Result<(), ClewdrError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(CookieEvent::SetWeight(cookie, weight, tx))?;
+        rx.await?
+    }
+
     /// Used for internal reset checking
     /// Sends a reset check event to the cookie manager
     ///
@@ -132,36 +321,48 @@ impl CookieManager {
     /// Initializes cookie collections, creates event channels and queues,
     /// and spawns the event processing task
     ///
+    /// # Arguments
+    /// * `state` - The pool state loaded at startup
+    /// * `writer` - Writer used to persist the cookie collections
+    ///
     /// # Returns
     /// * `CookieEventSender` - Event sender for interacting with the cookie manager
-    pub fn start() -> CookieEventSender {
+    pub fn start(state: &PoolState, writer: StateWriter) -> CookieEventSender {
         let valid = VecDeque::from_iter(
-            CLEWDR_CONFIG
-                .load()
+            state
                 .cookie_array
                 .iter()
                 .filter(|c| c.reset_time.is_none())
                 .cloned(),
         );
         let exhaust = HashSet::from_iter(
-            CLEWDR_CONFIG
-                .load()
+            state
                 .cookie_array
                 .iter()
                 .filter(|c| c.reset_time.is_some())
                 .cloned(),
         );
-        let invalid = HashSet::from_iter(CLEWDR_CONFIG.load().wasted_cookie.iter().cloned());
+        let invalid = state.wasted_cookie.to_owned();
 
         // 创建事件通道
         let (event_tx, event_rx) = mpsc::unbounded_channel();
 
-        let sender = CookieEventSender { sender: event_tx };
+        let sender = CookieEventSender {
+            sender: event_tx.to_owned(),
+        };
 
         let manager = Self {
             valid,
             exhausted: exhaust,
             invalid,
+            leases: HashMap::new(),
+            in_flight: HashMap::new(),
+            next_lease: 0,
+            waiting: VecDeque::new(),
+            affinity: HashMap::new(),
+            dirty: false,
+            writer,
+            event_tx,
             event_rx,
         };
         // 启动事件处理器
@@ -181,23 +382,17 @@ impl CookieManager {
         );
     }
 
-    /// Saves the current state of cookies to the configuration
-    /// Updates the cookie arrays in the config and writes to disk
+    /// Saves the current state of cookies to the state file
+    /// The write is debounced by the state writer
     fn save(&mut self) {
-        CLEWDR_CONFIG.rcu(|config| {
-            let mut config = ClewdrConfig::clone(config);
-            config.cookie_array = self
-                .valid
-                .iter()
-                .chain(self.exhausted.iter())
-                .cloned()
-                .collect();
-            config.wasted_cookie = self.invalid.to_owned();
-            config
-        });
-        CLEWDR_CONFIG.load().save().unwrap_or_else(|e| {
-            error!("Failed to save config: {}", e);
-        });
+        let cookies = self
+            .valid
+            .iter()
+            .chain(self.exhausted.iter())
+            .cloned()
+            .collect();
+        self.writer.save_cookies(cookies, self.invalid.to_owned());
+        self.dirty = false;
     }
 
     /// Checks and resets cookies that have passed their reset time
@@ -221,19 +416,315 @@ impl CookieManager {
         self.save();
     }
 
+    /// Gets the number of requests currently using a cookie
+    fn in_flight(&self, cookie: &CookieStatus) -> usize {
+        self.in_flight
+            .get(&cookie.cookie)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Checks if a cookie can serve a request right now
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to check
+    /// * `request` - Constraints on the cookie to hand out
+    /// * `max_concurrency` - Maximum number of leases per cookie, 0 for unlimited
+    fn available(
+        &self,
+        cookie: &CookieStatus,
+        request: &CookieRequest,
+        max_concurrency: usize,
+    ) -> bool {
+        request.allows(cookie) && (max_concurrency == 0 || self.in_flight(cookie) < max_concurrency)
+    }
+
+    /// Selects the position of the next cookie in the valid collection
+    /// Cookies outside the groups, excluded or at their concurrency limit are skipped,
+    /// ties are broken by the position in the queue, so equal cookies are rotated
+    ///
+    /// # Arguments
+    /// * `strategy` - The dispatch strategy to apply
+    /// * `request` - Constraints on the cookie to hand out
+    /// * `max_concurrency` - Maximum number of leases per cookie, 0 for unlimited
+    ///
+    /// # Returns
+    /// * `Option<usize>` - Position of the selected cookie, None if no cookie is available
+    fn select(
+        &self,
+        strategy: DispatchStrategy,
+        request: &CookieRequest,
+        max_concurrency: usize,
+    ) -> Option<usize> {
+        let candidates = self
+            .valid
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| self.available(c, request, max_concurrency))
+            .collect::<Vec<_>>();
+        let candidates = candidates.into_iter();
+        match strategy {
+            DispatchStrategy::RoundRobin => candidates.map(|(i, _)| i).next(),
+            DispatchStrategy::LeastRecentlyUsed => candidates
+                .min_by_key(|(_, c)| c.stats.last_used.unwrap_or_default())
+                .map(|(i, _)| i),
+            DispatchStrategy::LeastInFlight => candidates
+                .min_by_key(|(_, c)| self.in_flight(c))
+                .map(|(i, _)| i),
+            DispatchStrategy::RandomWeighted => {
+                let total = candidates
+                    .clone()
+                    .map(|(_, c)| c.weight as u64)
+                    .sum::<u64>();
+                if total == 0 {
+                    return candidates.map(|(i, _)| i).next();
+                }
+                let mut pick = rng().random_range(0..total);
+                candidates
+                    .filter(|(_, c)| c.weight > 0)
+                    .find(|(_, c)| {
+                        let weight = c.weight as u64;
+                        if pick < weight {
+                            return true;
+                        }
+                        pick -= weight;
+                        false
+                    })
+                    .map(|(i, _)| i)
+            }
+            // cookies not bootstrapped yet rank as Free, so that they get used and
+            // their tier is recorded
+            DispatchStrategy::PreferCapabilities => candidates
+                .min_by_key(|(_, c)| Reverse(c.tier().unwrap_or(PlanTier::Free)))
+                .map(|(i, _)| i),
+        }
+    }
+
+    /// Finds the cookie pinned to a conversation
+    /// The pin is ignored if it expired, or the cookie is no longer available
+    ///
+    /// # Arguments
+    /// * `fingerprint` - Fingerprint of the conversation
+    /// * `request` - Constraints on the cookie to hand out
+    /// * `max_concurrency` - Maximum number of leases per cookie, 0 for unlimited
+    ///
+    /// # Returns
+    /// * `Option<usize>` - Position of the pinned cookie in the valid collection
+    fn pinned(
+        &self,
+        fingerprint: u64,
+        request: &CookieRequest,
+        max_concurrency: usize,
+    ) -> Option<usize> {
+        let (cookie, expiry) = self.affinity.get(&fingerprint)?;
+        if *expiry <= chrono::Utc::now().timestamp() {
+            return None;
+        }
+        self.valid
+            .iter()
+            .position(|c| c.cookie == *cookie)
+            .filter(|&i| self.available(&self.valid[i], request, max_concurrency))
+    }
+
     /// Dispatches a cookie for use
-    /// Gets a cookie from the valid collection
+    /// Leases the cookie pinned to the conversation if it is still usable,
+    /// otherwise a cookie from the valid collection according to the configured strategy
+    ///
+    /// # Arguments
+    /// * `request` - Constraints on the cookie to hand out
     ///
     /// # Returns
-    /// * `Result<CookieStatus, ClewdrError>` - A cookie if available, error otherwise
-    fn dispatch(&mut self) -> Result<CookieStatus, ClewdrError> {
+    /// * `Result<Option<CookieLease>, ClewdrError>` - A lease if a cookie is available,
+    ///   None if all matching cookies are at their concurrency limit,
+    ///   error if no valid cookie matches the groups of the request without being excluded
+    fn dispatch(&mut self, request: &CookieRequest) -> Result<Option<CookieLease>, ClewdrError> {
         self.reset();
-        let cookie = self
-            .valid
-            .pop_front()
-            .ok_or(ClewdrError::NoCookieAvailable)?;
+        if !self.valid.iter().any(|c| request.allows(c)) {
+            return Err(ClewdrError::NoCookieAvailable);
+        }
+        let config = CLEWDR_CONFIG.load();
+        let max_concurrency = config.max_concurrency_per_cookie;
+        let fingerprint = request.fingerprint.filter(|_| config.affinity_ttl > 0);
+        if let Some(fingerprint) = fingerprint {
+            // a cookie that failed the conversation must not keep it
+            if self
+                .affinity
+                .get(&fingerprint)
+                .is_some_and(|(c, _)| request.exclude.contains(c))
+            {
+                self.affinity.remove(&fingerprint);
+            }
+        }
+        let Some(mut cookie) = fingerprint
+            .and_then(|f| self.pinned(f, request, max_concurrency))
+            .or_else(|| self.select(config.dispatch_strategy, request, max_concurrency))
+            .and_then(|i| self.valid.remove(i))
+        else {
+            return Ok(None);
+        };
+        if let Some(fingerprint) = fingerprint {
+            // pin the conversation, or move it to a new cookie if the old one is unusable
+            let expiry = chrono::Utc::now().timestamp() + config.affinity_ttl as i64;
+            self.affinity
+                .insert(fingerprint, (cookie.cookie.to_owned(), expiry));
+        }
+        cookie.stats.record_dispatch();
+        self.dirty = true;
         self.valid.push_back(cookie.to_owned());
-        Ok(cookie)
+        Ok(Some(self.new_lease(cookie)))
+    }
+
+    /// Creates a lease of a cookie, counting it as in flight until it is released
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to lease
+    ///
+    /// # Returns
+    /// * `CookieLease` - The lease of the cookie
+    fn new_lease(&mut self, cookie: CookieStatus) -> CookieLease {
+        let id = self.next_lease;
+        self.next_lease += 1;
+        self.leases.insert(id, cookie.cookie.to_owned());
+        *self.in_flight.entry(cookie.cookie.to_owned()).or_default() += 1;
+        CookieLease {
+            id,
+            cookie,
+            sender: self.event_tx.to_owned(),
+        }
+    }
+
+    /// Leases a specific valid or exhausted cookie if it is below its concurrency limit
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to lease
+    ///
+    /// # Returns
+    /// * `Option<CookieLease>` - The lease, None if the cookie is busy or not found
+    fn lease(&mut self, cookie: &ClewdrCookie) -> Option<CookieLease> {
+        let status = self
+            .valid
+            .iter()
+            .chain(self.exhausted.iter())
+            .find(|c| c.cookie == *cookie)?
+            .to_owned();
+        let max_concurrency = CLEWDR_CONFIG.load().max_concurrency_per_cookie;
+        if max_concurrency > 0 && self.in_flight(&status) >= max_concurrency {
+            return None;
+        }
+        Some(self.new_lease(status))
+    }
+
+    /// Releases the lease of a cookie that is no longer used by a request
+    ///
+    /// # Arguments
+    /// * `id` - Id of the lease being released
+    fn release(&mut self, id: u64) {
+        let Some(cookie) = self.leases.remove(&id) else {
+            return;
+        };
+        let Some(count) = self.in_flight.get_mut(&cookie) else {
+            return;
+        };
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            self.in_flight.remove(&cookie);
+        }
+    }
+
+    /// Gets the time the oldest waiting request times out
+    ///
+    /// # Returns
+    /// * `Option<Instant>` - The deadline, None if nothing waits or waiting is unlimited
+    fn next_deadline(&self) -> Option<Instant> {
+        let timeout = CLEWDR_CONFIG.load().cookie_wait_timeout;
+        if timeout == 0 {
+            return None;
+        }
+        // requests are queued in arrival order, so the front waited longest
+        let (since, _, _) = self.waiting.front()?;
+        Some(*since + Duration::from_secs(timeout))
+    }
+
+    /// Answers the requests that waited longer than the configured timeout
+    /// Requests whose caller is gone are dropped from the queue
+    fn expire_waiting(&mut self) {
+        let timeout = Duration::from_secs(CLEWDR_CONFIG.load().cookie_wait_timeout);
+        let now = Instant::now();
+        for (since, request, sender) in std::mem::take(&mut self.waiting) {
+            if sender.is_closed() {
+                continue;
+            }
+            if !timeout.is_zero() && now.duration_since(since) >= timeout {
+                warn!("No cookie available after waiting {}s", timeout.as_secs());
+                let _ = sender.send(Err(ClewdrError::NoCookieAvailable));
+                continue;
+            }
+            self.waiting.push_back((since, request, sender));
+        }
+    }
+
+    /// Serves the requests waiting for a cookie to become available
+    /// Requests that still cannot be served keep their place in the queue
+    fn serve_waiting(&mut self) {
+        let mut pending = VecDeque::new();
+        while let Some((since, request, sender)) = self.waiting.pop_front() {
+            if sender.is_closed() {
+                continue;
+            }
+            match self.dispatch(&request) {
+                Ok(Some(lease)) => {
+                    // a dropped receiver releases the lease again
+                    let _ = sender.send(Ok(lease));
+                }
+                Ok(None) => {
+                    pending.push_back((since, request, sender));
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                }
+            }
+        }
+        self.waiting = pending;
+    }
+
+    /// Records the outcome of a request in the statistics of the stored cookie
+    /// The returned cookie is updated with the accumulated statistics
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie being returned
+    /// * `reason` - Optional reason the cookie was returned with
+    /// * `usage` - Optional outcome of the request
+    fn record(
+        &mut self,
+        cookie: &mut CookieStatus,
+        reason: Option<&Reason>,
+        usage: Option<CookieUsage>,
+    ) {
+        let update = |stored: &mut CookieStatus, cookie: &mut CookieStatus| {
+            stored.stats.record_return(reason, usage);
+            if !cookie.capabilities.is_empty() {
+                stored.capabilities = cookie.capabilities.to_owned();
+            }
+            if let Some(account) = cookie.account.take() {
+                match stored.account.as_mut() {
+                    Some(stored) => stored.merge(account),
+                    None => stored.account = Some(account),
+                }
+            }
+            cookie.stats = stored.stats.to_owned();
+            cookie.weight = stored.weight;
+            cookie.groups = stored.groups.to_owned();
+            cookie.account = stored.account.to_owned();
+        };
+        if let Some(stored) = self.valid.iter_mut().find(|c| *c == cookie) {
+            update(stored, cookie);
+        } else if let Some(mut stored) = self.exhausted.take(cookie) {
+            update(&mut stored, cookie);
+            self.exhausted.insert(stored);
+        } else {
+            return;
+        }
+        self.dirty = true;
     }
 
     /// Collects a returned cookie and processes it based on the return reason
@@ -241,12 +732,31 @@ impl CookieManager {
     /// # Arguments
     /// * `cookie` - The cookie being returned
     /// * `reason` - Optional reason for the return that determines how the cookie is processed
-    fn collect(&mut self, mut cookie: CookieStatus, reason: Option<Reason>) {
+    /// * `usage` - Optional outcome of the request, recorded in the cookie statistics
+    fn collect(
+        &mut self,
+        mut cookie: CookieStatus,
+        reason: Option<Reason>,
+        usage: Option<CookieUsage>,
+    ) {
+        self.record(&mut cookie, reason.as_ref(), usage);
         let Some(reason) = reason else {
             return;
         };
+        let event = match reason {
+            Reason::TooManyRequest(i) | Reason::Restricted(i) => WebhookEvent::CookieExhausted {
+                cookie: cookie.cookie.ellipse(),
+                reason: reason.to_string(),
+                reset_time: Some(i),
+            },
+            _ => WebhookEvent::CookieInvalid {
+                cookie: cookie.cookie.ellipse(),
+                reason: reason.to_string(),
+            },
+        };
         let mut find_remove = |cookie: &CookieStatus| {
             self.valid.retain(|c| c != cookie);
+            self.exhausted.remove(cookie);
         };
         match reason {
             Reason::NormalPro => {
@@ -270,7 +780,7 @@ impl CookieManager {
                 find_remove(&cookie);
                 if !self
                     .invalid
-                    .insert(UselessCookie::new(cookie.cookie, reason))
+                    .insert(UselessCookie::from_status(cookie, reason))
                 {
                     return;
                 }
@@ -279,7 +789,7 @@ impl CookieManager {
                 find_remove(&cookie);
                 if !self
                     .invalid
-                    .insert(UselessCookie::new(cookie.cookie, reason))
+                    .insert(UselessCookie::from_status(cookie, reason))
                 {
                     return;
                 }
@@ -287,6 +797,53 @@ impl CookieManager {
         }
         self.save();
         self.log();
+        WEBHOOK.notify(event);
+        let threshold = CLEWDR_CONFIG.load().webhook.pool_threshold;
+        if self.valid.len() < threshold {
+            WEBHOOK.notify(WebhookEvent::PoolLow {
+                valid: self.valid.len(),
+                threshold,
+            });
+        }
+    }
+
+    /// Moves a revalidated cookie out of the invalid collection
+    /// Cookies that passed are restored to the valid collection,
+    /// rate limited or restricted cookies are moved to the exhausted collection,
+    /// others stay invalid with the new reason
+    ///
+    /// # Arguments
+    /// * `cookie` - The bootstrapped cookie
+    /// * `reason` - Reason the cookie is still unusable, None if it passed
+    fn revalidated(&mut self, mut cookie: CookieStatus, reason: Option<Reason>) {
+        let useless = UselessCookie::new(cookie.cookie.to_owned(), Reason::Null);
+        let Some(old) = self.invalid.take(&useless) else {
+            return;
+        };
+        cookie.stats = old.stats;
+        if let Some(mut account) = old.account {
+            if let Some(newer) = cookie.account.take() {
+                account.merge(newer);
+            }
+            cookie.account = Some(account);
+        }
+        match reason {
+            None => {
+                info!("Cookie restored: {}", cookie.cookie.ellipse().green());
+                cookie.reset_time = None;
+                self.valid.push_back(cookie);
+            }
+            Some(Reason::TooManyRequest(i)) | Some(Reason::Restricted(i)) => {
+                cookie.reset_time = Some(i);
+                self.exhausted.insert(cookie);
+            }
+            Some(reason) => {
+                self.invalid
+                    .insert(UselessCookie::from_status(cookie, reason));
+            }
+        }
+        self.save();
+        self.log();
     }
 
     /// Accepts a new cookie into the valid collection
@@ -295,21 +852,60 @@ impl CookieManager {
     /// # Arguments
     /// * `cookie` - The new cookie to accept
     fn accept(&mut self, cookie: CookieStatus) {
-        if CLEWDR_CONFIG.load().cookie_array.contains(&cookie)
-            || CLEWDR_CONFIG
-                .load()
-                .wasted_cookie
-                .iter()
-                .any(|c| *c == cookie)
-        {
-            warn!("Cookie already exists");
+        let status = self.insert(cookie);
+        if status != ImportStatus::Accepted {
+            warn!("Cookie not accepted: {}", status);
             return;
         }
-        self.valid.push_back(cookie.to_owned());
         self.save();
         self.log();
     }
 
+    /// Adds a new cookie to the valid or exhausted collection without saving
+    ///
+    /// # Arguments
+    /// * `cookie` - The new cookie to add
+    ///
+    /// # Returns
+    /// * `ImportStatus` - Whether the cookie was added, or why it was not
+    fn insert(&mut self, cookie: CookieStatus) -> ImportStatus {
+        if !cookie.cookie.validate() {
+            return ImportStatus::Malformed;
+        }
+        if self.invalid.iter().any(|c| *c == cookie) {
+            return ImportStatus::Wasted;
+        }
+        if self.valid.contains(&cookie) || self.exhausted.contains(&cookie) {
+            return ImportStatus::Duplicate;
+        }
+        // a rate limited cookie waits for its reset time
+        if cookie.reset_time.is_some() {
+            self.exhausted.insert(cookie);
+        } else {
+            self.valid.push_back(cookie);
+        }
+        ImportStatus::Accepted
+    }
+
+    /// Imports a batch of cookies into the valid collection
+    ///
+    /// # Arguments
+    /// * `cookies` - The cookies to import
+    ///
+    /// # Returns
+    /// * `Vec<ImportStatus>` - Outcome of each cookie, in order
+    fn import(&mut self, cookies: Vec<CookieStatus>) -> Vec<ImportStatus> {
+        let results = cookies
+            .into_iter()
+            .map(|c| self.insert(c))
+            .collect::<Vec<_>>();
+        if results.contains(&ImportStatus::Accepted) {
+            self.save();
+            self.log();
+        }
+        results
+    }
+
     /// Creates a report of all cookie statuses
     ///
     /// # Returns
@@ -350,6 +946,58 @@ impl CookieManager {
         }
     }
 
+    /// Sets the groups of a valid or exhausted cookie
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to update
+    /// * `groups` - The new groups of the cookie
+    ///
+    /// # Returns
+    /// * `Result<(), ClewdrError>` - Success or error if the cookie is not found
+    fn set_groups(&mut self, cookie: ClewdrCookie, groups: Vec<String>) -> Result<(), ClewdrError> {
+        if let Some(stored) = self.valid.iter_mut().find(|c| c.cookie == cookie) {
+            stored.groups = groups;
+        } else if let Some(mut stored) = self.exhausted.take(&CookieStatus {
+            cookie,
+            ..Default::default()
+        }) {
+            stored.groups = groups;
+            self.exhausted.insert(stored);
+        } else {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "Set groups operation did not find the cookie",
+            });
+        }
+        self.save();
+        Ok(())
+    }
+
+    /// Sets the dispatch weight of a valid or exhausted cookie
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to update
+    /// * `weight` - The new weight of the cookie
+    ///
+    /// # Returns
+    /// * `Result<(), ClewdrError>` - Success or error if the cookie is not found
+    fn set_weight(&mut self, cookie: ClewdrCookie, weight: u32) -> Result<(), ClewdrError> {
+        if let Some(stored) = self.valid.iter_mut().find(|c| c.cookie == cookie) {
+            stored.weight = weight;
+        } else if let Some(mut stored) = self.exhausted.take(&CookieStatus {
+            cookie,
+            ..Default::default()
+        }) {
+            stored.weight = weight;
+            self.exhausted.insert(stored);
+        } else {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "Set weight operation did not find the cookie",
+            });
+        }
+        self.save();
+        Ok(())
+    }
+
     /// Spawns a task to listen for timer events and send timeout check events
     ///
     /// # Arguments
@@ -379,27 +1027,86 @@ impl CookieManager {
 
         // 事件处理主循环
         self.log();
-        while let Some(res) = self.event_rx.recv().await {
+        loop {
+            // 等待事件, 或最早的等待请求超时
+            let event = match self.next_deadline() {
+                Some(deadline) => match timeout_at(deadline, self.event_rx.recv()).await {
+                    Ok(event) => event,
+                    Err(_) => {
+                        self.expire_waiting();
+                        continue;
+                    }
+                },
+                None => self.event_rx.recv().await,
+            };
+            let Some(res) = event else {
+                break;
+            };
             // 尝试从队列中获取事件
             match res {
                 // 处理事件
-                CookieEvent::Return(cookie, reason) => {
+                CookieEvent::Return(cookie, reason, usage) => {
                     // 处理返回的cookie (最高优先级)
-                    self.collect(cookie, reason);
+                    self.collect(cookie, reason, usage);
+                }
+                CookieEvent::Release(id) => {
+                    self.release(id);
                 }
                 CookieEvent::Submit(cookie) => {
                     // 处理提交的新cookie (次高优先级)
                     self.accept(cookie);
                 }
+                CookieEvent::Import(cookies, sender) => {
+                    let results = self.import(cookies);
+                    sender.send(results).unwrap_or_else(|_| {
+                        error!("Failed to send import results");
+                    });
+                }
                 CookieEvent::CheckReset => {
                     // 处理超时检查 (中等优先级)
                     self.reset();
+                    // 清理过期的会话绑定
+                    let now = chrono::Utc::now().timestamp();
+                    self.affinity.retain(|_, (_, expiry)| *expiry > now);
                 }
-                CookieEvent::Request(sender) => {
+                CookieEvent::Request(request, sender) => {
                     // 处理请求 (最低优先级)
-                    let cookie = self.dispatch();
-                    sender.send(cookie).unwrap_or_else(|_| {
-                        error!("Failed to send cookie");
+                    match self.dispatch(&request) {
+                        Ok(Some(lease)) => {
+                            // a dropped receiver releases the lease again
+                            if sender.send(Ok(lease)).is_err() {
+                                error!("Failed to send cookie");
+                            }
+                        }
+                        Ok(None) => {
+                            // all cookies are busy, wait for a release
+                            self.waiting.push_back((Instant::now(), request, sender));
+                        }
+                        Err(e) => {
+                            sender.send(Err(e)).unwrap_or_else(|_| {
+                                error!("Failed to send cookie");
+                            });
+                        }
+                    }
+                }
+                CookieEvent::Lease(cookie, sender) => {
+                    let lease = self.lease(&cookie);
+                    // a dropped receiver releases the lease again
+                    let _ = sender.send(lease);
+                }
+                CookieEvent::Revalidated(cookie, reason) => {
+                    self.revalidated(cookie, reason);
+                }
+                CookieEvent::SetGroups(cookie, groups, sender) => {
+                    let result = self.set_groups(cookie, groups);
+                    sender.send(result).unwrap_or_else(|_| {
+                        error!("Failed to send set groups result");
+                    });
+                }
+                CookieEvent::SetWeight(cookie, weight, sender) => {
+                    let result = self.set_weight(cookie, weight);
+                    sender.send(result).unwrap_or_else(|_| {
+                        error!("Failed to send set weight result");
                     });
                 }
                 CookieEvent::GetStatus(sender) => {
@@ -415,6 +1122,14 @@ impl CookieManager {
                     });
                 }
             }
+            if !self.waiting.is_empty() {
+                self.expire_waiting();
+                self.serve_waiting();
+            }
+            // 保存统计信息, 写入由状态写入器合并
+            if self.dirty {
+                self.save();
+            }
         }
     }
 }
@@ -0,0 +1,302 @@
+use colored::Colorize;
+use futures::{StreamExt, stream};
+use rand::{Rng, rng};
+use serde::Serialize;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{spawn, time::sleep};
+use tracing::{info, warn};
+
+use crate::{
+    claude_state::ClaudeState,
+    config::{CLEWDR_CONFIG, ClewdrCookie, CookieStatus, Reason, UselessCookie},
+    error::ClewdrError,
+};
+
+/// Interval to wait before looking at the config again while health checks are disabled
+const DISABLED_INTERVAL: u64 = 60;
+/// Maximum random delay before each cookie is checked, in milliseconds
+const MAX_JITTER: u64 = 5000;
+
+/// Where an invalid cookie ended up after being revalidated
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RevalidateStatus {
+    /// The cookie passed and is valid again
+    Restored,
+    /// The cookie is rate limited or restricted, it is valid again once the reset time passes
+    Exhausted,
+    /// The cookie is still unusable
+    Invalid,
+    /// The cookie could not be checked, it was left untouched
+    Error,
+}
+
+/// Result of revalidating a single invalid cookie
+#[derive(Debug, Serialize, Clone)]
+pub struct RevalidateResult {
+    pub cookie: ClewdrCookie,
+    /// Reason the cookie was invalid before
+    pub previous: Reason,
+    pub status: RevalidateStatus,
+    /// Reason the cookie is still unusable, if any
+    pub reason: Option<Reason>,
+    /// Error that prevented the check, if any
+    pub error: Option<String>,
+}
+
+/// Background checker that bootstraps every cookie to find broken ones
+/// before a user request hits them
+#[derive(Clone)]
+pub struct CookieHealthChecker {
+    state: ClaudeState,
+    running: Arc<AtomicBool>,      // Whether a check round is in progress
+    revalidating: Arc<AtomicBool>, // Whether invalid cookies are being revalidated
+}
+
+impl CookieHealthChecker {
+    /// Starts the health checker
+    /// Runs a check round every `health_check_interval` seconds, if the interval is not 0
+    ///
+    /// # Arguments
+    /// * `state` - Claude state used to bootstrap the cookies
+    ///
+    /// # Returns
+    /// * `CookieHealthChecker` - Handle used to trigger a check round manually
+    pub fn start(state: ClaudeState) -> Self {
+        let checker = Self {
+            state,
+            running: Arc::new(AtomicBool::new(false)),
+            revalidating: Arc::new(AtomicBool::new(false)),
+        };
+        let handle = checker.to_owned();
+        spawn(async move {
+            loop {
+                let interval = CLEWDR_CONFIG.load().revalidate_interval;
+                if interval == 0 {
+                    sleep(Duration::from_secs(DISABLED_INTERVAL)).await;
+                    continue;
+                }
+                sleep(Duration::from_secs(interval)).await;
+                handle.revalidate().await;
+            }
+        });
+        let handle = checker.to_owned();
+        spawn(async move {
+            loop {
+                let interval = CLEWDR_CONFIG.load().health_check_interval;
+                if interval == 0 {
+                    sleep(Duration::from_secs(DISABLED_INTERVAL)).await;
+                    continue;
+                }
+                sleep(Duration::from_secs(interval)).await;
+                handle.run().await;
+            }
+        });
+        checker
+    }
+
+    /// Triggers a check round in the background
+    ///
+    /// # Returns
+    /// * `bool` - False if a check round is already in progress
+    pub fn trigger(&self) -> bool {
+        if self.running.load(Ordering::Acquire) {
+            return false;
+        }
+        let checker = self.to_owned();
+        spawn(async move { checker.run().await });
+        true
+    }
+
+    /// Bootstraps every valid and exhausted cookie and reports the result to the cookie manager
+    async fn run(&self) {
+        if self.running.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        scopeguard::defer! {
+            self.running.store(false, Ordering::Release);
+        }
+        let status = match self.state.event_sender.get_status().await {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("Failed to get cookie status for health check: {}", e);
+                return;
+            }
+        };
+        let cookies = status
+            .valid
+            .into_iter()
+            .chain(status.exhausted)
+            .collect::<Vec<_>>();
+        info!(
+            "[HEALTH] checking {} cookies",
+            cookies.len().to_string().green()
+        );
+        let concurrency = CLEWDR_CONFIG.load().health_check_concurrency.max(1);
+        stream::iter(cookies)
+            .for_each_concurrent(concurrency, |cookie| self.check(cookie))
+            .await;
+        info!("[HEALTH] check finished");
+    }
+
+    /// Bootstraps every invalid cookie again and restores the ones that pass
+    ///
+    /// # Returns
+    /// * `Option<Vec<RevalidateResult>>` - Result of each invalid cookie,
+    ///   None if a revalidation is already in progress
+    pub async fn revalidate(&self) -> Option<Vec<RevalidateResult>> {
+        if self.revalidating.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        scopeguard::defer! {
+            self.revalidating.store(false, Ordering::Release);
+        }
+        let status = match self.state.event_sender.get_status().await {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("Failed to get cookie status for revalidation: {}", e);
+                return Some(vec![]);
+            }
+        };
+        info!(
+            "[HEALTH] revalidating {} invalid cookies",
+            status.invalid.len().to_string().red()
+        );
+        let concurrency = CLEWDR_CONFIG.load().health_check_concurrency.max(1);
+        let results = stream::iter(status.invalid)
+            .map(|cookie| self.revalidate_one(cookie))
+            .buffer_unordered(concurrency)
+            .collect::<Vec<_>>()
+            .await;
+        let restored = results
+            .iter()
+            .filter(|r| r.status == RevalidateStatus::Restored)
+            .count();
+        info!(
+            "[HEALTH] revalidation finished, {} cookies restored",
+            restored.to_string().green()
+        );
+        Some(results)
+    }
+
+    /// Bootstraps a single invalid cookie after a random delay
+    ///
+    /// # Arguments
+    /// * `useless` - The invalid cookie to check
+    ///
+    /// # Returns
+    /// * `RevalidateResult` - Where the cookie ended up
+    async fn revalidate_one(&self, useless: UselessCookie) -> RevalidateResult {
+        let jitter = rng().random_range(0..MAX_JITTER);
+        sleep(Duration::from_millis(jitter)).await;
+        let mut result = RevalidateResult {
+            cookie: useless.cookie.to_owned(),
+            previous: useless.reason.to_owned(),
+            status: RevalidateStatus::Error,
+            reason: None,
+            error: None,
+        };
+        let mut state = self.state.to_owned();
+        if let Err(e) = state.set_cookie(useless.to_status()) {
+            result.error = Some(e.to_string());
+            return result;
+        }
+        let reason = match state.bootstrap().await {
+            Ok(_) => None,
+            Err(ClewdrError::InvalidCookie { reason }) => Some(reason),
+            Err(e) => {
+                warn!(
+                    "[HEALTH] failed to revalidate {}: {}",
+                    useless.cookie.ellipse(),
+                    e
+                );
+                result.error = Some(e.to_string());
+                return result;
+            }
+        };
+        result.status = match reason {
+            None => RevalidateStatus::Restored,
+            Some(Reason::TooManyRequest(_)) | Some(Reason::Restricted(_)) => {
+                RevalidateStatus::Exhausted
+            }
+            Some(_) => RevalidateStatus::Invalid,
+        };
+        result.reason = reason.to_owned();
+        let cookie = state
+            .cookie
+            .to_owned()
+            .unwrap_or_else(|| useless.to_status());
+        if let Err(e) = self.state.event_sender.revalidated(cookie, reason).await {
+            result.status = RevalidateStatus::Error;
+            result.error = Some(e.to_string());
+        }
+        result
+    }
+
+    /// Checks a single cookie after a random delay
+    /// Cookies at their concurrency limit are skipped until the next round
+    ///
+    /// # Arguments
+    /// * `cookie` - The cookie to check
+    async fn check(&self, cookie: CookieStatus) {
+        let jitter = rng().random_range(0..MAX_JITTER);
+        sleep(Duration::from_millis(jitter)).await;
+        let lease = match self
+            .state
+            .event_sender
+            .lease(cookie.cookie.to_owned())
+            .await
+        {
+            Ok(Some(lease)) => lease,
+            Ok(None) => {
+                info!("[HEALTH] skipping busy {}", cookie.cookie.ellipse());
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "[HEALTH] failed to lease {}: {}",
+                    cookie.cookie.ellipse(),
+                    e
+                );
+                return;
+            }
+        };
+        // the lease is released when the state is dropped
+        let cookie = lease.cookie.to_owned();
+        let mut state = self.state.to_owned();
+        state.lease = Some(Arc::new(lease));
+        if let Err(e) = state.set_cookie(cookie.to_owned()) {
+            warn!(
+                "[HEALTH] failed to prepare {}: {}",
+                cookie.cookie.ellipse(),
+                e
+            );
+            return;
+        }
+        match state.bootstrap().await {
+            // updates the capabilities of the cookie
+            Ok(_) => state.return_cookie(None, None).await,
+            Err(ClewdrError::InvalidCookie { reason }) => {
+                warn!(
+                    "[HEALTH] {}: {}",
+                    cookie.cookie.ellipse().yellow(),
+                    reason.to_string().red()
+                );
+                state.return_cookie(Some(reason), None).await;
+            }
+            Err(e) => {
+                warn!(
+                    "[HEALTH] failed to check {}: {}",
+                    cookie.cookie.ellipse(),
+                    e
+                );
+            }
+        }
+    }
+}
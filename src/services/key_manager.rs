@@ -1,27 +1,35 @@
 use colored::Colorize;
 use serde::Serialize;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use tokio::{
     spawn,
     sync::{mpsc, oneshot},
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
-    config::{CLEWDR_CONFIG, ClewdrConfig, KeyStatus},
+    config::{CLEWDR_CONFIG, KeyFailure, KeyStatus, KeyUsage, PoolState, UselessKey},
     error::ClewdrError,
+    services::{
+        state_writer::StateWriter,
+        webhook::{WEBHOOK, WebhookEvent},
+    },
 };
 
 #[derive(Debug, Serialize, Clone)]
 pub struct KeyStatusInfo {
     pub valid: Vec<KeyStatus>,
+    pub exhausted: Vec<KeyStatus>,
+    pub invalid: Vec<UselessKey>,
 }
 
 /// Unified event enum for key management
 #[derive(Debug)]
 pub enum KeyEvent {
-    /// Return a Key
-    Return(KeyStatus),
+    /// Return a Key, with the failure it caused if any
+    Return(KeyStatus, Option<KeyFailure>),
+    /// Record the token usage of a response served with a Key
+    Usage(KeyStatus, KeyUsage),
     /// Submit a new Key
     Submit(KeyStatus),
     /// Request to get a Key
@@ -35,6 +43,9 @@ pub enum KeyEvent {
 /// Key manager that handles key distribution and status tracking
 pub struct KeyManager {
     valid: VecDeque<KeyStatus>,
+    exhausted: HashSet<KeyStatus>, // Rate limited keys waiting for their reset time
+    invalid: HashSet<UselessKey>,  // Keys moved out of rotation
+    writer: StateWriter,           // Writer of the pool state file
     event_rx: mpsc::UnboundedReceiver<KeyEvent>, // Event receiver for incoming events
 }
 
@@ -59,11 +70,29 @@ impl KeyEventSender {
     ///
     /// # Arguments
     /// * `key` - The key to return
+    /// * `failure` - Failure caused by the key, None if the request succeeded
     ///
     /// # Returns
     /// Result indicating success or send error
-    pub async fn return_key(&self, key: KeyStatus) -> Result<(), ClewdrError> {
-        Ok(self.sender.send(KeyEvent::Return(key))?)
+    pub async fn return_key(
+        &self,
+        key: KeyStatus,
+        failure: Option<KeyFailure>,
+    ) -> Result<(), ClewdrError> {
+        Ok(self.sender.send(KeyEvent::Return(key, failure))?)
+    }
+
+    /// Record the token usage of a response served with a key
+    /// Not async, so it can be called when a response stream is dropped
+    ///
+    /// # Arguments
+    /// * `key` - The key that served the response
+    /// * `usage` - Token usage reported by Gemini
+    ///
+    /// # Returns
+    /// Result indicating success or send error
+    pub fn record_usage(&self, key: KeyStatus, usage: KeyUsage) -> Result<(), ClewdrError> {
+        Ok(self.sender.send(KeyEvent::Usage(key, usage))?)
     }
 
     /// Submit a new key to the key manager
@@ -107,17 +136,41 @@ impl KeyManager {
     /// Initializes key collections, creates event channels and queues,
     /// and spawns the event processing task
     ///
+    /// # Arguments
+    /// * `state` - The pool state loaded at startup
+    /// * `writer` - Writer used to persist the keys
+    ///
     /// # Returns
     /// * `KeyEventSender` - Event sender for interacting with the key manager
-    pub fn start() -> KeyEventSender {
-        let valid = VecDeque::from_iter(CLEWDR_CONFIG.load().gemini_keys.iter().cloned());
+    pub fn start(state: &PoolState, writer: StateWriter) -> KeyEventSender {
+        let valid = VecDeque::from_iter(
+            state
+                .gemini_keys
+                .iter()
+                .filter(|k| k.reset_time.is_none())
+                .cloned(),
+        );
+        let exhausted = HashSet::from_iter(
+            state
+                .gemini_keys
+                .iter()
+                .filter(|k| k.reset_time.is_some())
+                .cloned(),
+        );
+        let invalid = state.wasted_keys.to_owned();
 
         // Create event channel
         let (event_tx, event_rx) = mpsc::unbounded_channel();
 
         let sender = KeyEventSender { sender: event_tx };
 
-        let manager = Self { valid, event_rx };
+        let manager = Self {
+            valid,
+            exhausted,
+            invalid,
+            writer,
+            event_rx,
+        };
         // Start event processor
         spawn(manager.run());
 
@@ -127,20 +180,44 @@ impl KeyManager {
     /// Logs the current state of key collections
     /// Displays count of valid keys
     fn log(&self) {
-        info!("Valid Keys: {}", self.valid.len().to_string().green());
+        info!(
+            "Valid Keys: {}, Exhausted: {}, Invalid: {}",
+            self.valid.len().to_string().green(),
+            self.exhausted.len().to_string().yellow(),
+            self.invalid.len().to_string().red(),
+        );
     }
 
-    /// Saves the current state of keys to the configuration
-    /// Updates the key arrays in the config and writes to disk
+    /// Saves the current state of keys to the state file
+    /// The write is debounced by the state writer
     fn save(&mut self) {
-        CLEWDR_CONFIG.rcu(|config| {
-            let mut config = ClewdrConfig::clone(config);
-            config.gemini_keys = self.valid.iter().cloned().collect();
-            config
-        });
-        CLEWDR_CONFIG.load().save().unwrap_or_else(|e| {
-            error!("Failed to save config: {}", e);
+        let keys = self
+            .valid
+            .iter()
+            .chain(self.exhausted.iter())
+            .cloned()
+            .collect();
+        self.writer.save_keys(keys, self.invalid.to_owned());
+    }
+
+    /// Moves keys whose cooldown has expired back to the valid collection
+    fn reset(&mut self) {
+        let mut reset_keys = Vec::new();
+        self.exhausted.retain(|key| {
+            let reset = key.to_owned().reset();
+            if reset.reset_time.is_none() {
+                reset_keys.push(reset);
+                false
+            } else {
+                true
+            }
         });
+        if reset_keys.is_empty() {
+            return;
+        }
+        self.valid.extend(reset_keys);
+        self.save();
+        self.log();
     }
 
     /// Dispatches a key for use
@@ -149,42 +226,135 @@ impl KeyManager {
     /// # Returns
     /// * `Result<KeyStatus, ClewdrError>` - A key if available, error otherwise
     fn dispatch(&mut self) -> Result<KeyStatus, ClewdrError> {
-        let key = self.valid.pop_front().ok_or(ClewdrError::NoKeyAvailable)?;
+        self.reset();
+        let mut key = self.valid.pop_front().ok_or(ClewdrError::NoKeyAvailable)?;
+        key.stats.record_dispatch();
         self.valid.push_back(key.to_owned());
+        self.save();
         Ok(key)
     }
 
-    fn collect(&mut self, key: KeyStatus) {
-        // find the key and replace it
+    /// Adds the token usage of a response to the daily statistics of a key
+    ///
+    /// # Arguments
+    /// * `key` - The key that served the response
+    /// * `usage` - Token usage reported by Gemini
+    fn record_usage(&mut self, key: KeyStatus, usage: KeyUsage) {
+        if let Some(stored) = self.valid.iter_mut().find(|k| **k == key) {
+            stored.stats.record_usage(usage);
+        } else if let Some(mut stored) = self.exhausted.take(&key) {
+            stored.stats.record_usage(usage);
+            self.exhausted.insert(stored);
+        } else {
+            return;
+        }
+        self.save();
+    }
+
+    /// Collects a returned key and updates its state according to the failure
+    /// Rate limited keys are put on cooldown, keys that keep failing
+    /// authorization are moved to the invalid collection,
+    /// keys that served a request clear their unauthorized responses
+    ///
+    /// # Arguments
+    /// * `key` - The returned key
+    /// * `failure` - Failure caused by the key, None if the request succeeded
+    fn collect(&mut self, key: KeyStatus, failure: Option<KeyFailure>) {
+        let Some(failure) = failure else {
+            // only consecutive 403s count towards the thresholds
+            if let Some(stored) = self
+                .valid
+                .iter_mut()
+                .find(|k| **k == key && k.count_403 > 0)
+            {
+                stored.count_403 = 0;
+                self.save();
+            }
+            return;
+        };
+        // the stored key is authoritative, the returned one may be outdated
         let Some(pos) = self.valid.iter().position(|k| *k == key) else {
-            error!("Key not found in valid keys");
+            info!("Key {} is no longer valid", key.key.ellipse());
             return;
         };
-        self.valid[pos] = key;
+        let config = CLEWDR_CONFIG.load();
+        match failure {
+            KeyFailure::RateLimited(reset_time) => {
+                let Some(mut key) = self.valid.remove(pos) else {
+                    return;
+                };
+                let reset_time = reset_time
+                    .unwrap_or_else(|| chrono::Utc::now().timestamp() + config.key_cooldown as i64);
+                info!(
+                    "Key {} rate limited until {}",
+                    key.key.ellipse(),
+                    reset_time.to_string().yellow()
+                );
+                key.reset_time = Some(reset_time);
+                self.exhausted.insert(key);
+            }
+            KeyFailure::Unauthorized => {
+                self.valid[pos].count_403 += 1;
+                let key = self.valid[pos].to_owned();
+                let threshold = config.webhook.key_403_threshold;
+                if threshold > 0 && key.count_403 >= threshold {
+                    WEBHOOK.notify(WebhookEvent::KeyForbidden {
+                        key: key.key.ellipse(),
+                        count_403: key.count_403,
+                    });
+                }
+                let threshold = config.key_invalid_threshold;
+                if threshold > 0 && key.count_403 >= threshold {
+                    self.valid.remove(pos);
+                    warn!("Key {} moved to invalid", key.key.ellipse());
+                    let reason = format!("{} unauthorized responses", key.count_403);
+                    self.invalid.insert(UselessKey::new(key, reason));
+                }
+            }
+        }
+        self.save();
+        self.log();
     }
 
-    /// Accepts a new key into the valid collection
+    /// Accepts a new key into the valid collection, or the exhausted one if it is rate limited
     /// Checks for duplicates before adding
     ///
     /// # Arguments
     /// * `key` - The new key to accept
     fn accept(&mut self, key: KeyStatus) {
-        if CLEWDR_CONFIG.load().gemini_keys.contains(&key) {
+        if self.valid.contains(&key)
+            || self.exhausted.contains(&key)
+            || self.invalid.iter().any(|k| k.key == key.key)
+        {
             info!("Key already exists");
             return;
         }
-        self.valid.push_back(key);
+        // a rate limited key waits for its reset time
+        if key.reset_time.is_some() {
+            self.exhausted.insert(key);
+        } else {
+            self.valid.push_back(key);
+        }
         self.save();
         self.log();
     }
 
+    /// Copies a key with statistics of a previous day cleared
+    fn today(key: &KeyStatus) -> KeyStatus {
+        let mut key = key.to_owned();
+        key.stats.rollover();
+        key
+    }
+
     /// Creates a report of all key statuses
     ///
     /// # Returns
     /// * `KeyStatusInfo` - Information about all key collections
     fn report(&self) -> KeyStatusInfo {
         KeyStatusInfo {
-            valid: self.valid.iter().cloned().collect(),
+            valid: self.valid.iter().map(Self::today).collect(),
+            exhausted: self.exhausted.iter().map(Self::today).collect(),
+            invalid: self.invalid.iter().cloned().collect(),
         }
     }
 
@@ -196,11 +366,13 @@ impl KeyManager {
     /// # Returns
     /// * `Result<(), ClewdrError>` - Success if found and deleted, error otherwise
     fn delete(&mut self, key: KeyStatus) -> Result<(), ClewdrError> {
-        let size_before = self.valid.len();
+        let size_before = self.valid.len() + self.exhausted.len() + self.invalid.len();
         self.valid.retain(|k| *k != key);
+        self.exhausted.retain(|k| *k != key);
+        self.invalid.retain(|k| k.key != key.key);
 
-        if self.valid.len() < size_before {
-            // Update config to reflect changes
+        if self.valid.len() + self.exhausted.len() + self.invalid.len() < size_before {
+            // Update state to reflect changes
             self.save();
             self.log();
             Ok(())
@@ -221,9 +393,12 @@ impl KeyManager {
         self.log();
         while let Some(event) = self.event_rx.recv().await {
             match event {
-                KeyEvent::Return(key) => {
+                KeyEvent::Return(key, failure) => {
                     // Process returned key
-                    self.collect(key);
+                    self.collect(key, failure);
+                }
+                KeyEvent::Usage(key, usage) => {
+                    self.record_usage(key, usage);
                 }
                 KeyEvent::Submit(key) => {
                     // Process submitted new key
@@ -1,4 +1,11 @@
 pub mod cache;
+pub mod conversation;
 pub mod cookie_manager;
-pub mod update;
+pub mod health_check;
 pub mod key_manager;
+pub mod probe;
+pub mod state_writer;
+pub mod token_cache;
+pub mod update;
+pub mod vertex_manager;
+pub mod webhook;
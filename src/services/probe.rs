@@ -0,0 +1,212 @@
+use rquest::ClientBuilder;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{
+    claude_state::ClaudeState,
+    config::{CLEWDR_CONFIG, CookieStatus, GEMINI_ENDPOINT, GeminiKey, KeyFailure, Reason},
+    error::{CheckGeminiErr, ClewdrError},
+    gemini_state::key_failure,
+};
+
+/// Diagnosis of a probed key or cookie
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeStatus {
+    /// The upstream accepted the key or cookie
+    Valid,
+    /// The key or cookie works but is rate limited for now
+    RateLimited,
+    /// The cookie belongs to a restricted or unsuitable account
+    Restricted,
+    /// The upstream rejected the key or cookie
+    Invalid,
+    /// The probe could not reach a verdict, e.g. a network error
+    Error,
+}
+
+/// Result of probing a key or cookie against the upstream
+#[derive(Debug, Serialize, Clone)]
+pub struct ProbeResult {
+    /// Whether the key or cookie can be used right away
+    pub ok: bool,
+    pub status: ProbeStatus,
+    /// HTTP status returned by the upstream, if any
+    pub code: Option<u16>,
+    /// Error message returned by the upstream, or of the failed probe
+    pub message: Option<String>,
+    /// Reason a cookie is unusable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<Reason>,
+    /// Timestamp at which a rate limited key or cookie can be used again
+    pub reset_time: Option<i64>,
+    /// Number of models visible to a Gemini key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models: Option<usize>,
+    /// Capabilities of the account behind a cookie
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+}
+
+impl ProbeResult {
+    fn new(status: ProbeStatus) -> Self {
+        Self {
+            ok: status == ProbeStatus::Valid,
+            status,
+            code: None,
+            message: None,
+            reason: None,
+            reset_time: None,
+            models: None,
+            capabilities: Vec::new(),
+        }
+    }
+
+    /// Checks if the key or cookie may be added to the pool
+    ///
+    /// Rate limited or temporarily restricted ones are added with their reset time,
+    /// and inconclusive probes are given the benefit of the doubt. Only keys and
+    /// cookies rejected by the upstream or permanently unsuitable are refused.
+    ///
+    /// # Returns
+    /// True if the key or cookie should be accepted
+    pub fn accepted(&self) -> bool {
+        match self.status {
+            ProbeStatus::Valid | ProbeStatus::RateLimited | ProbeStatus::Error => true,
+            // a restriction without an end, e.g. a free account, is permanent
+            ProbeStatus::Restricted => self.reset_time.is_some(),
+            ProbeStatus::Invalid => false,
+        }
+    }
+
+    fn error(e: impl ToString) -> Self {
+        Self {
+            message: Some(e.to_string()),
+            ..Self::new(ProbeStatus::Error)
+        }
+    }
+}
+
+/// Probes a Gemini key by listing the models visible to it
+///
+/// # Arguments
+/// * `key` - The key to probe
+///
+/// # Returns
+/// * `ProbeResult` - Diagnosis of the key
+pub async fn probe_key(key: &GeminiKey) -> ProbeResult {
+    let client = ClientBuilder::new();
+    let client = if let Some(proxy) = CLEWDR_CONFIG.load().proxy.to_owned() {
+        client.proxy(proxy)
+    } else {
+        client
+    };
+    let client = match client.build() {
+        Ok(client) => client,
+        Err(e) => return ProbeResult::error(e),
+    };
+    let res = client
+        .get(format!("{}/v1beta/models", GEMINI_ENDPOINT))
+        .query(&[("key", key.to_string())])
+        .send()
+        .await;
+    let res = match res {
+        Ok(res) => res.check_gemini().await,
+        Err(e) => return ProbeResult::error(e),
+    };
+    let result = match res {
+        Ok(res) => {
+            let code = res.status().as_u16();
+            let models = res
+                .json::<Value>()
+                .await
+                .ok()
+                .and_then(|v| v["models"].as_array().map(|m| m.len()));
+            ProbeResult {
+                code: Some(code),
+                models,
+                ..ProbeResult::new(ProbeStatus::Valid)
+            }
+        }
+        Err(ClewdrError::GeminiHttpError { code, inner }) => {
+            let status = match key_failure(code.as_u16(), &inner) {
+                Some(KeyFailure::RateLimited(reset_time)) => ProbeResult {
+                    reset_time,
+                    ..ProbeResult::new(ProbeStatus::RateLimited)
+                },
+                Some(KeyFailure::Unauthorized) => ProbeResult::new(ProbeStatus::Invalid),
+                None => ProbeResult::new(ProbeStatus::Error),
+            };
+            ProbeResult {
+                code: Some(code.as_u16()),
+                message: inner["error"]["message"]
+                    .as_str()
+                    .or(inner["message"].as_str())
+                    .map(|m| m.to_string()),
+                ..status
+            }
+        }
+        Err(e) => ProbeResult::error(e),
+    };
+    info!("[PROBE] key {}: {:?}", key.ellipse(), result.status);
+    result
+}
+
+/// Probes a Claude cookie by bootstrapping its account
+///
+/// # Arguments
+/// * `state` - Claude state used to bootstrap the cookie
+/// * `cookie` - The cookie to probe
+///
+/// # Returns
+/// * `(ProbeResult, CookieStatus)` - Diagnosis of the cookie, and the cookie
+///   updated with the capabilities found during the bootstrap and its reset time
+pub async fn probe_cookie(
+    mut state: ClaudeState,
+    cookie: CookieStatus,
+) -> (ProbeResult, CookieStatus) {
+    if let Err(e) = state.set_cookie(cookie.to_owned()) {
+        return (ProbeResult::error(e), cookie);
+    }
+    let result = match state.bootstrap().await {
+        Ok(_) => ProbeResult {
+            capabilities: state.capabilities.to_owned(),
+            ..ProbeResult::new(ProbeStatus::Valid)
+        },
+        Err(ClewdrError::InvalidCookie { reason }) => {
+            let status = match reason.to_owned() {
+                Reason::TooManyRequest(reset_time) => ProbeResult {
+                    reset_time: Some(reset_time),
+                    ..ProbeResult::new(ProbeStatus::RateLimited)
+                },
+                Reason::Restricted(reset_time) => ProbeResult {
+                    reset_time: Some(reset_time),
+                    ..ProbeResult::new(ProbeStatus::Restricted)
+                },
+                Reason::NonPro => ProbeResult::new(ProbeStatus::Restricted),
+                _ => ProbeResult::new(ProbeStatus::Invalid),
+            };
+            ProbeResult {
+                reason: Some(reason),
+                ..status
+            }
+        }
+        Err(e) => {
+            warn!(
+                "[PROBE] failed to bootstrap {}: {}",
+                cookie.cookie.ellipse(),
+                e
+            );
+            ProbeResult::error(e)
+        }
+    };
+    info!(
+        "[PROBE] cookie {}: {:?}",
+        cookie.cookie.ellipse(),
+        result.status
+    );
+    let mut cookie = state.cookie.to_owned().unwrap_or(cookie);
+    cookie.reset_time = result.reset_time;
+    (result, cookie)
+}
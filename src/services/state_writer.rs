@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+use tokio::{
+    spawn,
+    sync::{mpsc, oneshot},
+    task::spawn_blocking,
+    time::{Duration, Instant, sleep_until},
+};
+use tracing::error;
+
+use crate::config::{
+    CookieStatus, KeyStatus, PoolState, UselessCookie, UselessKey, VertexCredential,
+};
+
+/// Time to wait for further updates before writing the state file
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Update of one part of the pool state
+enum StateUpdate {
+    /// Cookie collections changed
+    Cookies(HashSet<CookieStatus>, HashSet<UselessCookie>),
+    /// Gemini keys changed
+    Keys(HashSet<KeyStatus>, HashSet<UselessKey>),
+    /// Vertex credentials changed
+    Vertex(HashSet<VertexCredential>),
+    /// Write the state right away, acknowledged once written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Handle used by the managers to persist the pool state
+///
+/// Updates are coalesced and written in the background, so a burst of
+/// changes results in a single write of the state file.
+#[derive(Clone)]
+pub struct StateWriter {
+    sender: mpsc::UnboundedSender<StateUpdate>,
+}
+
+impl StateWriter {
+    /// Starts the state writer
+    ///
+    /// # Arguments
+    /// * `state` - The pool state loaded at startup
+    ///
+    /// # Returns
+    /// * `StateWriter` - Handle for sending updates to the writer
+    pub fn start(state: PoolState) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        spawn(Self::run(state, receiver));
+        Self { sender }
+    }
+
+    /// Schedules a write of the cookie collections
+    ///
+    /// # Arguments
+    /// * `cookies` - Valid and exhausted cookies
+    /// * `wasted` - Invalid cookies
+    pub fn save_cookies(&self, cookies: HashSet<CookieStatus>, wasted: HashSet<UselessCookie>) {
+        if self
+            .sender
+            .send(StateUpdate::Cookies(cookies, wasted))
+            .is_err()
+        {
+            error!("State writer is not running");
+        }
+    }
+
+    /// Schedules a write of the Gemini keys
+    ///
+    /// # Arguments
+    /// * `keys` - Valid and exhausted keys
+    /// * `wasted` - Invalid keys
+    pub fn save_keys(&self, keys: HashSet<KeyStatus>, wasted: HashSet<UselessKey>) {
+        if self.sender.send(StateUpdate::Keys(keys, wasted)).is_err() {
+            error!("State writer is not running");
+        }
+    }
+
+    /// Schedules a write of the Vertex credentials
+    ///
+    /// # Arguments
+    /// * `credentials` - All Vertex credentials
+    pub fn save_vertex(&self, credentials: HashSet<VertexCredential>) {
+        if self.sender.send(StateUpdate::Vertex(credentials)).is_err() {
+            error!("State writer is not running");
+        }
+    }
+
+    /// Writes the pending updates without waiting for the debounce deadline
+    /// Used on shutdown, so the last changes are not lost
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.sender.send(StateUpdate::Flush(tx)).is_err() || rx.await.is_err() {
+            error!("State writer is not running");
+        }
+    }
+
+    /// Applies an update to the state
+    ///
+    /// # Returns
+    /// * `Option<oneshot::Sender<()>>` - The acknowledgement of a flush, None for other updates
+    fn apply(state: &mut PoolState, update: StateUpdate) -> Option<oneshot::Sender<()>> {
+        match update {
+            StateUpdate::Cookies(cookies, wasted) => {
+                state.cookie_array = cookies;
+                state.wasted_cookie = wasted;
+            }
+            StateUpdate::Keys(keys, wasted) => {
+                state.gemini_keys = keys;
+                state.wasted_keys = wasted;
+            }
+            StateUpdate::Vertex(credentials) => {
+                state.vertex_credentials = credentials;
+            }
+            StateUpdate::Flush(ack) => return Some(ack),
+        }
+        None
+    }
+
+    /// Writes the state file without blocking the runtime
+    async fn write(state: &PoolState) {
+        let state = state.to_owned();
+        match spawn_blocking(move || state.save()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Failed to save state: {}", e),
+            Err(e) => error!("State writer task failed: {}", e),
+        }
+    }
+
+    /// Main loop of the writer
+    /// Waits for an update, collects further updates until the debounce
+    /// deadline or a flush, then writes the state once
+    async fn run(mut state: PoolState, mut receiver: mpsc::UnboundedReceiver<StateUpdate>) {
+        while let Some(update) = receiver.recv().await {
+            let mut flush = Self::apply(&mut state, update);
+            let deadline = Instant::now() + DEBOUNCE;
+            let mut closed = false;
+            while flush.is_none() {
+                tokio::select! {
+                    update = receiver.recv() => match update {
+                        Some(update) => flush = Self::apply(&mut state, update),
+                        None => {
+                            closed = true;
+                            break;
+                        }
+                    },
+                    _ = sleep_until(deadline) => break,
+                }
+            }
+            Self::write(&state).await;
+            if let Some(ack) = flush {
+                // the caller may have given up waiting
+                let _ = ack.send(());
+            }
+            if closed {
+                break;
+            }
+        }
+    }
+}
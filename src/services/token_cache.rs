@@ -0,0 +1,165 @@
+use hyper_util::client::legacy::connect::HttpConnector;
+use snafu::ResultExt;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use tokio::{spawn, sync::Mutex};
+use tracing::{info, warn};
+use yup_oauth2::{CustomHyperClientBuilder, ServiceAccountAuthenticator, ServiceAccountKey};
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    error::{ClewdrError, InvalidUriSnafu},
+};
+
+/// Global cache of Vertex access tokens, shared by all requests
+pub static VERTEX_TOKENS: LazyLock<TokenCache> = LazyLock::new(TokenCache::default);
+
+/// Tokens closer than this to their expiry are not used anymore, in seconds
+const EXPIRY_MARGIN: i64 = 60;
+/// Tokens closer than this to their expiry are refreshed in the background, in seconds
+const REFRESH_MARGIN: i64 = 300;
+/// Lifetime assumed for tokens without an expiry, in seconds
+const DEFAULT_LIFETIME: i64 = 3600;
+
+/// An access token and the timestamp at which it expires
+#[derive(Clone)]
+struct CachedToken {
+    token: String,
+    expires_at: i64,
+}
+
+/// Cached token of a single service account
+///
+/// The mutex is held while a token is fetched, so concurrent requests
+/// wait for the same exchange instead of starting their own.
+#[derive(Default)]
+struct TokenEntry {
+    token: Mutex<Option<CachedToken>>,
+    refreshing: AtomicBool,
+}
+
+/// Cache of OAuth access tokens keyed by service account
+#[derive(Default)]
+pub struct TokenCache {
+    entries: std::sync::Mutex<HashMap<String, Arc<TokenEntry>>>,
+}
+
+/// Identifies a service account, a new private key gets a new token
+fn cache_key(sa_key: &ServiceAccountKey) -> String {
+    format!(
+        "{}/{}",
+        sa_key.client_email,
+        sa_key.private_key_id.as_deref().unwrap_or_default()
+    )
+}
+
+impl TokenCache {
+    /// Gets the entry of a service account, creating it if needed
+    fn entry(&self, sa_key: &ServiceAccountKey) -> Arc<TokenEntry> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.entry(cache_key(sa_key)).or_default().to_owned()
+    }
+
+    /// Gets an access token for a service account
+    ///
+    /// Returns the cached token while it is valid, and starts a background
+    /// refresh when it is about to expire.
+    ///
+    /// # Arguments
+    /// * `sa_key` - The service account key
+    ///
+    /// # Returns
+    /// * `Result<String, ClewdrError>` - The access token
+    pub async fn get(&self, sa_key: &ServiceAccountKey) -> Result<String, ClewdrError> {
+        let entry = self.entry(sa_key);
+        let mut cached = entry.token.lock().await;
+        let now = chrono::Utc::now().timestamp();
+        if let Some(token) = cached
+            .as_ref()
+            .filter(|t| t.expires_at - now > EXPIRY_MARGIN)
+        {
+            if token.expires_at - now < REFRESH_MARGIN
+                && !entry.refreshing.swap(true, Ordering::AcqRel)
+            {
+                spawn(refresh(entry.to_owned(), sa_key.to_owned()));
+            }
+            return Ok(token.token.to_owned());
+        }
+        let token = fetch_token(sa_key.to_owned()).await?;
+        *cached = Some(token.to_owned());
+        Ok(token.token)
+    }
+
+    /// Drops the cached token of a service account
+    /// Used when Vertex rejects a token before its expiry
+    ///
+    /// # Arguments
+    /// * `sa_key` - The service account key
+    pub async fn invalidate(&self, sa_key: &ServiceAccountKey) {
+        let entry = self.entry(sa_key);
+        entry.token.lock().await.take();
+    }
+}
+
+/// Refreshes the token of an entry in the background
+async fn refresh(entry: Arc<TokenEntry>, sa_key: ServiceAccountKey) {
+    match fetch_token(sa_key.to_owned()).await {
+        Ok(token) => {
+            *entry.token.lock().await = Some(token);
+            info!("[VERTEX] refreshed token of {}", sa_key.client_email);
+        }
+        Err(e) => {
+            warn!(
+                "[VERTEX] failed to refresh token of {}: {}",
+                sa_key.client_email, e
+            );
+        }
+    }
+    entry.refreshing.store(false, Ordering::Release);
+}
+
+/// Exchanges a service account key for an access token
+async fn fetch_token(sa_key: ServiceAccountKey) -> Result<CachedToken, ClewdrError> {
+    const SCOPES: [&str; 1] = ["https://www.googleapis.com/auth/cloud-platform"];
+    let token = if let Some(proxy) = CLEWDR_CONFIG.load().proxy.to_owned() {
+        let proxy = proxy
+            .trim_start_matches("http://")
+            .trim_start_matches("https://")
+            .trim_start_matches("socks5://");
+        let proxy = format!("http://{}", proxy);
+        let proxy_uri = proxy.parse().context(InvalidUriSnafu {
+            uri: proxy.to_owned(),
+        })?;
+        let proxy = hyper_http_proxy::Proxy::new(hyper_http_proxy::Intercept::All, proxy_uri);
+        let connector = HttpConnector::new();
+        let proxy_connector = hyper_http_proxy::ProxyConnector::from_proxy(connector, proxy)?;
+        let client =
+            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+                .pool_max_idle_per_host(0)
+                .build(proxy_connector);
+        let client_builder = CustomHyperClientBuilder::from(client);
+        let auth = ServiceAccountAuthenticator::with_client(sa_key, client_builder)
+            .build()
+            .await?;
+        auth.token(&SCOPES).await?
+    } else {
+        let auth = ServiceAccountAuthenticator::builder(sa_key).build().await?;
+        auth.token(&SCOPES).await?
+    };
+    let expires_at = token
+        .expiration_time()
+        .map(|t| t.unix_timestamp())
+        .unwrap_or_else(|| chrono::Utc::now().timestamp() + DEFAULT_LIFETIME);
+    let token = token.token().ok_or(ClewdrError::UnexpectedNone {
+        msg: "Oauth token is None",
+    })?;
+    Ok(CachedToken {
+        token: token.into(),
+        expires_at,
+    })
+}
@@ -0,0 +1,387 @@
+use colored::Colorize;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use tokio::{
+    spawn,
+    sync::{mpsc, oneshot},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{
+        CLEWDR_CONFIG, ClewdrConfig, KeyFailure, PoolState, VertexCredential, VertexCredentialInfo,
+    },
+    error::ClewdrError,
+    services::{
+        state_writer::StateWriter,
+        webhook::{WEBHOOK, WebhookEvent},
+    },
+};
+
+#[derive(Debug, Serialize, Clone)]
+pub struct VertexStatusInfo {
+    pub valid: Vec<VertexCredentialInfo>,
+    pub exhausted: Vec<VertexCredentialInfo>,
+}
+
+/// Unified event enum for Vertex credential management
+#[derive(Debug)]
+pub enum VertexEvent {
+    /// Return a credential, with the failure it caused if any
+    Return(String, Option<KeyFailure>),
+    /// Submit a new credential, or update the overrides of an existing one
+    Submit(Box<VertexCredential>),
+    /// Request to get a credential
+    Request(oneshot::Sender<Result<VertexCredential, ClewdrError>>),
+    /// Get all credential status information
+    GetStatus(oneshot::Sender<VertexStatusInfo>),
+    /// Delete a credential by its client email
+    Delete(String, oneshot::Sender<Result<(), ClewdrError>>),
+}
+
+/// Vertex manager that rotates service accounts and tracks their failures
+pub struct VertexManager {
+    valid: VecDeque<VertexCredential>,
+    exhausted: HashSet<VertexCredential>, // Credentials waiting for their reset time
+    writer: StateWriter,                  // Writer of the pool state file
+    event_rx: mpsc::UnboundedReceiver<VertexEvent>, // Event receiver for incoming events
+}
+
+/// Event sender interface provided for external components to interact with the Vertex manager
+#[derive(Clone)]
+pub struct VertexEventSender {
+    sender: mpsc::UnboundedSender<VertexEvent>,
+}
+
+impl VertexEventSender {
+    /// Request a credential from the Vertex manager
+    ///
+    /// # Returns
+    /// * `Result<VertexCredential, ClewdrError>` - Credential if available, error otherwise
+    pub async fn request(&self) -> Result<VertexCredential, ClewdrError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(VertexEvent::Request(tx))?;
+        rx.await?
+    }
+
+    /// Return a credential to the Vertex manager
+    ///
+    /// # Arguments
+    /// * `id` - Client email of the credential
+    /// * `failure` - Failure caused by the credential, if any
+    ///
+    /// # Returns
+    /// Result indicating success or send error
+    pub async fn return_credential(
+        &self,
+        id: String,
+        failure: Option<KeyFailure>,
+    ) -> Result<(), ClewdrError> {
+        Ok(self.sender.send(VertexEvent::Return(id, failure))?)
+    }
+
+    /// Submit a new credential to the Vertex manager
+    ///
+    /// # Arguments
+    /// * `credential` - The credential to add or update
+    ///
+    /// # Returns
+    /// Result indicating success or send error
+    pub async fn submit(&self, credential: VertexCredential) -> Result<(), ClewdrError> {
+        Ok(self
+            .sender
+            .send(VertexEvent::Submit(Box::new(credential)))?)
+    }
+
+    /// Get status information about all credentials
+    ///
+    /// # Returns
+    /// * `Result<VertexStatusInfo, ClewdrError>` - Status information without private keys
+    pub async fn get_status(&self) -> Result<VertexStatusInfo, ClewdrError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(VertexEvent::GetStatus(tx))?;
+        Ok(rx.await?)
+    }
+
+    /// Delete a credential from the Vertex manager
+    ///
+    /// # Arguments
+    /// * `id` - Client email of the credential to delete
+    ///
+    /// # Returns
+    /// * `Result<(), ClewdrError>` - Success or error
+    pub async fn delete_credential(&self, id: String) -> Result<(), ClewdrError> {
+        let (tx, rx) = oneshot::channel();
+        self.sender.send(VertexEvent::Delete(id, tx))?;
+        rx.await?
+    }
+}
+
+impl VertexManager {
+    /// Starts the Vertex manager and returns an event sender
+    ///
+    /// # Arguments
+    /// * `state` - The pool state loaded at startup
+    /// * `writer` - Writer used to persist the credentials
+    ///
+    /// # Returns
+    /// * `VertexEventSender` - Event sender for interacting with the Vertex manager
+    pub fn start(state: &PoolState, writer: StateWriter) -> VertexEventSender {
+        let valid = VecDeque::from_iter(
+            state
+                .vertex_credentials
+                .iter()
+                .filter(|c| c.reset_time.is_none())
+                .cloned(),
+        );
+        let exhausted = HashSet::from_iter(
+            state
+                .vertex_credentials
+                .iter()
+                .filter(|c| c.reset_time.is_some())
+                .cloned(),
+        );
+
+        // Create event channel
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
+        let sender = VertexEventSender { sender: event_tx };
+
+        let mut manager = Self {
+            valid,
+            exhausted,
+            writer,
+            event_rx,
+        };
+        manager.absorb_config();
+        // Start event processor
+        spawn(manager.run());
+
+        sender
+    }
+
+    /// Logs the current state of credential collections
+    fn log(&self) {
+        info!(
+            "Vertex credentials: {}, Cooling down: {}",
+            self.valid.len().to_string().green(),
+            self.exhausted.len().to_string().yellow(),
+        );
+    }
+
+    /// Saves the current state of credentials to the state file
+    /// The write is debounced by the state writer
+    fn save(&mut self) {
+        let credentials = self
+            .valid
+            .iter()
+            .chain(self.exhausted.iter())
+            .cloned()
+            .collect();
+        self.writer.save_vertex(credentials);
+    }
+
+    /// Moves a credential set in the config into the pool
+    /// Covers credentials from older configs, the environment and the config API
+    fn absorb_config(&mut self) {
+        let config = CLEWDR_CONFIG.load();
+        let Some(credential) = config.vertex.credential.to_owned() else {
+            return;
+        };
+        let credential = VertexCredential::new(credential, None);
+        info!("Vertex credential {} added from config", credential.id());
+        self.accept(credential);
+        CLEWDR_CONFIG.rcu(|config| {
+            let mut config = ClewdrConfig::clone(config);
+            config.vertex.credential = None;
+            config
+        });
+        CLEWDR_CONFIG.load().save().unwrap_or_else(|e| {
+            error!("Failed to save config: {}", e);
+        });
+    }
+
+    /// Moves credentials whose cooldown has expired back to the valid collection
+    fn reset(&mut self) {
+        let mut reset = Vec::new();
+        self.exhausted.retain(|credential| {
+            let credential = credential.to_owned().reset();
+            if credential.reset_time.is_none() {
+                reset.push(credential);
+                false
+            } else {
+                true
+            }
+        });
+        if reset.is_empty() {
+            return;
+        }
+        self.valid.extend(reset);
+        self.save();
+        self.log();
+    }
+
+    /// Dispatches a credential for use, rotating through the valid collection
+    ///
+    /// # Returns
+    /// * `Result<VertexCredential, ClewdrError>` - A credential if available, error otherwise
+    fn dispatch(&mut self) -> Result<VertexCredential, ClewdrError> {
+        self.absorb_config();
+        self.reset();
+        let credential = self
+            .valid
+            .pop_front()
+            .ok_or(ClewdrError::NoVertexAvailable)?;
+        self.valid.push_back(credential.to_owned());
+        Ok(credential)
+    }
+
+    /// Collects a returned credential and updates its state according to the failure
+    /// Rate limited credentials are put on cooldown right away, others after
+    /// `key_invalid_threshold` failures, an alert is sent when no valid credential is left
+    ///
+    /// # Arguments
+    /// * `id` - Client email of the returned credential
+    /// * `failure` - Failure caused by the credential, if any
+    fn collect(&mut self, id: String, failure: Option<KeyFailure>) {
+        let Some(failure) = failure else {
+            return;
+        };
+        let Some(pos) = self.valid.iter().position(|c| c.id() == id) else {
+            info!("Vertex credential {} is no longer valid", id);
+            return;
+        };
+        let config = CLEWDR_CONFIG.load();
+        let now = chrono::Utc::now().timestamp();
+        let reset_time = match failure {
+            KeyFailure::RateLimited(reset_time) => {
+                Some(reset_time.unwrap_or(now + config.key_cooldown as i64))
+            }
+            KeyFailure::Unauthorized => {
+                self.valid[pos].count_failure += 1;
+                let threshold = config.key_invalid_threshold;
+                (threshold > 0 && self.valid[pos].count_failure >= threshold)
+                    .then_some(now + config.key_cooldown as i64)
+            }
+        };
+        let Some(reset_time) = reset_time else {
+            self.save();
+            return;
+        };
+        let Some(mut credential) = self.valid.remove(pos) else {
+            return;
+        };
+        warn!(
+            "Vertex credential {} cooling down until {}",
+            id,
+            reset_time.to_string().yellow()
+        );
+        credential.reset_time = Some(reset_time);
+        self.exhausted.insert(credential);
+        self.save();
+        self.log();
+        // a single failing credential is covered by the others, only alert once none is left
+        if self.valid.is_empty() {
+            let cause = match failure {
+                KeyFailure::RateLimited(_) => "rate limited",
+                KeyFailure::Unauthorized => "unauthorized",
+            };
+            WEBHOOK.notify(WebhookEvent::VertexFailing {
+                error: format!("{} is {}, no valid credential left", id, cause),
+            });
+        }
+    }
+
+    /// Accepts a new credential, or updates the overrides of an existing one
+    ///
+    /// # Arguments
+    /// * `credential` - The credential to accept
+    fn accept(&mut self, credential: VertexCredential) {
+        if let Some(existing) = self.valid.iter_mut().find(|c| **c == credential) {
+            existing.credential = credential.credential;
+            existing.model_id = credential.model_id;
+            existing.location = credential.location;
+        } else if let Some(mut existing) = self.exhausted.take(&credential) {
+            existing.credential = credential.credential;
+            existing.model_id = credential.model_id;
+            existing.location = credential.location;
+            self.exhausted.insert(existing);
+        } else {
+            self.valid.push_back(VertexCredential {
+                count_failure: 0,
+                reset_time: None,
+                ..credential
+            });
+        }
+        self.save();
+        self.log();
+    }
+
+    /// Creates a report of all credentials without their private keys
+    ///
+    /// # Returns
+    /// * `VertexStatusInfo` - Information about all credential collections
+    fn report(&mut self) -> VertexStatusInfo {
+        self.absorb_config();
+        VertexStatusInfo {
+            valid: self.valid.iter().map(VertexCredential::info).collect(),
+            exhausted: self.exhausted.iter().map(VertexCredential::info).collect(),
+        }
+    }
+
+    /// Deletes a credential from all collections
+    ///
+    /// # Arguments
+    /// * `id` - Client email of the credential to delete
+    ///
+    /// # Returns
+    /// * `Result<(), ClewdrError>` - Success if found and deleted, error otherwise
+    fn delete(&mut self, id: String) -> Result<(), ClewdrError> {
+        let size_before = self.valid.len() + self.exhausted.len();
+        self.valid.retain(|c| c.id() != id);
+        self.exhausted.retain(|c| c.id() != id);
+
+        if self.valid.len() + self.exhausted.len() < size_before {
+            self.save();
+            self.log();
+            Ok(())
+        } else {
+            Err(ClewdrError::UnexpectedNone {
+                msg: "Delete operation did not find the credential",
+            })
+        }
+    }
+
+    /// Main event processing loop
+    async fn run(mut self) {
+        self.log();
+        while let Some(event) = self.event_rx.recv().await {
+            match event {
+                VertexEvent::Return(id, failure) => {
+                    self.collect(id, failure);
+                }
+                VertexEvent::Submit(credential) => {
+                    self.accept(*credential);
+                }
+                VertexEvent::Request(sender) => {
+                    let credential = self.dispatch();
+                    sender.send(credential).unwrap_or_else(|_| {
+                        error!("Failed to send Vertex credential");
+                    });
+                }
+                VertexEvent::GetStatus(sender) => {
+                    let status_info = self.report();
+                    sender.send(status_info).unwrap_or_else(|_| {
+                        error!("Failed to send status info");
+                    });
+                }
+                VertexEvent::Delete(id, sender) => {
+                    let result = self.delete(id);
+                    sender.send(result).unwrap_or_else(|_| {
+                        error!("Failed to send delete result");
+                    });
+                }
+            }
+        }
+    }
+}
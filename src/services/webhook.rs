@@ -0,0 +1,209 @@
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+use tokio::{spawn, time::sleep};
+use tracing::{info, warn};
+
+use crate::config::{CLEWDR_CONFIG, WebhookFormat, WebhookTarget};
+
+/// Global webhook notifier
+///
+/// Shared by the cookie manager, the key manager and the Gemini state,
+/// so rate limiting applies across all of them.
+pub static WEBHOOK: LazyLock<WebhookNotifier> = LazyLock::new(WebhookNotifier::default);
+
+/// An event of the cookie or key pools that is worth alerting about
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A cookie was moved to the invalid collection
+    CookieInvalid { cookie: String, reason: String },
+    /// A cookie was moved to the exhausted collection
+    CookieExhausted {
+        cookie: String,
+        reason: String,
+        reset_time: Option<i64>,
+    },
+    /// The number of valid cookies dropped below the threshold
+    PoolLow { valid: usize, threshold: usize },
+    /// A key reached the 403 threshold
+    KeyForbidden { key: String, count_403: u32 },
+    /// The last valid Vertex credential was put on cooldown
+    VertexFailing { error: String },
+}
+
+impl WebhookEvent {
+    /// Kind of the event, used for rate limiting
+    fn kind(&self) -> &'static str {
+        match self {
+            WebhookEvent::CookieInvalid { .. } => "cookie_invalid",
+            WebhookEvent::CookieExhausted { .. } => "cookie_exhausted",
+            WebhookEvent::PoolLow { .. } => "pool_low",
+            WebhookEvent::KeyForbidden { .. } => "key_forbidden",
+            WebhookEvent::VertexFailing { .. } => "vertex_failing",
+        }
+    }
+}
+
+impl Display for WebhookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookEvent::CookieInvalid { cookie, reason } => {
+                write!(f, "Cookie {} is invalid: {}", cookie, reason)
+            }
+            WebhookEvent::CookieExhausted { cookie, reason, .. } => {
+                write!(f, "Cookie {} is exhausted: {}", cookie, reason)
+            }
+            WebhookEvent::PoolLow { valid, threshold } => write!(
+                f,
+                "Only {} valid cookies left (threshold {})",
+                valid, threshold
+            ),
+            WebhookEvent::KeyForbidden { key, count_403 } => {
+                write!(f, "Key {} received {} 403 responses", key, count_403)
+            }
+            WebhookEvent::VertexFailing { error } => {
+                write!(f, "Vertex credential is failing: {}", error)
+            }
+        }
+    }
+}
+
+/// Rate limiting state of an event kind
+#[derive(Default)]
+struct KindState {
+    last_sent: i64,
+    suppressed: usize,
+}
+
+/// Sends pool events to the configured webhooks
+///
+/// Events of the same kind are sent at most once per `min_interval`,
+/// the number of suppressed events is reported with the next one.
+#[derive(Default)]
+pub struct WebhookNotifier {
+    kinds: Mutex<HashMap<&'static str, KindState>>,
+}
+
+impl WebhookNotifier {
+    /// Notifies all configured webhooks of an event in the background
+    ///
+    /// # Arguments
+    /// * `event` - The event to send
+    pub fn notify(&self, event: WebhookEvent) {
+        let config = CLEWDR_CONFIG.load();
+        if config.webhook.targets.is_empty() {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp();
+        let suppressed = {
+            let Ok(mut kinds) = self.kinds.lock() else {
+                return;
+            };
+            let state = kinds.entry(event.kind()).or_default();
+            if now - state.last_sent < config.webhook.min_interval as i64 {
+                state.suppressed += 1;
+                return;
+            }
+            state.last_sent = now;
+            std::mem::take(&mut state.suppressed)
+        };
+        for target in config.webhook.targets.iter().cloned() {
+            let payload = payload(&target, &event, suppressed, now);
+            spawn(deliver(target, payload, config.webhook.max_retries));
+        }
+    }
+}
+
+/// Builds the payload of an event for a webhook
+///
+/// # Arguments
+/// * `target` - The webhook to build the payload for
+/// * `event` - The event to send
+/// * `suppressed` - Number of events of the same kind suppressed since the last one
+/// * `now` - Timestamp of the event
+///
+/// # Returns
+/// * `Value` - The JSON body of the request
+fn payload(target: &WebhookTarget, event: &WebhookEvent, suppressed: usize, now: i64) -> Value {
+    let mut text = format!("[ClewdR] {}", event);
+    if suppressed > 0 {
+        text += &format!(" ({} similar events suppressed)", suppressed);
+    }
+    match target.format {
+        WebhookFormat::Json => json!({
+            "event": event,
+            "message": text,
+            "suppressed": suppressed,
+            "timestamp": now,
+        }),
+        WebhookFormat::Discord => json!({
+            "content": text,
+        }),
+        WebhookFormat::Telegram => json!({
+            "chat_id": target.chat_id,
+            "text": text,
+        }),
+    }
+}
+
+/// Sends a payload to a webhook, retrying with exponential backoff
+/// on network errors, rate limits and server errors
+///
+/// # Arguments
+/// * `target` - The webhook to send to
+/// * `payload` - The JSON body of the request
+/// * `max_retries` - Maximum number of retries
+async fn deliver(target: WebhookTarget, payload: Value, max_retries: usize) {
+    let mut client = rquest::Client::builder();
+    if let Some(proxy) = CLEWDR_CONFIG.load().rquest_proxy.to_owned() {
+        client = client.proxy(proxy);
+    }
+    let Ok(client) = client.build() else {
+        warn!("[WEBHOOK] failed to build client");
+        return;
+    };
+    // the url of a webhook often embeds its secret token, so only the host is logged
+    let host = redact(&target.url);
+    for i in 0..=max_retries {
+        if i > 0 {
+            sleep(Duration::from_secs(1 << i.min(6))).await;
+        }
+        match client.post(&target.url).json(&payload).send().await {
+            Ok(res) if res.status().is_success() => {
+                info!("[WEBHOOK] delivered to {}", host);
+                return;
+            }
+            Ok(res) => {
+                let status = res.status();
+                warn!("[WEBHOOK] {} returned {}", host, status);
+                if !status.is_server_error() && status.as_u16() != 429 {
+                    return;
+                }
+            }
+            Err(e) => {
+                warn!("[WEBHOOK] failed to send to {}: {}", host, e.without_url());
+            }
+        }
+    }
+    warn!("[WEBHOOK] giving up on {}", host);
+}
+
+/// Redacts a webhook url for logging
+///
+/// # Arguments
+/// * `url` - The url of the webhook
+///
+/// # Returns
+/// * `String` - The host of the url, without the path and query holding the token
+fn redact(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(ToOwned::to_owned))
+        .unwrap_or_else(|| "<invalid url>".to_string())
+}
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::hash::{DefaultHasher, Hash, Hasher};
 
-use crate::config::CLEWDR_CONFIG;
+use crate::{config::CLEWDR_CONFIG, utils::count_tokens};
 
 #[derive(Debug)]
 pub struct RequiredMessageParams {
@@ -59,6 +60,17 @@ pub struct CreateMessageParams {
     /// extra body for Gemini
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extra_body: Option<serde_json::Value>,
+    /// Streaming options of OpenAI requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+}
+
+/// Streaming options of OpenAI requests
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct StreamOptions {
+    /// Whether to send a chunk with the token usage before the end of the stream
+    #[serde(default)]
+    pub include_usage: bool,
 }
 
 impl CreateMessageParams {
@@ -85,15 +97,90 @@ impl CreateMessageParams {
         }
         self.model = format!("google/{}", self.model);
     }
+
+    /// Checks if the model may call the tools of the request
+    pub fn uses_tools(&self) -> bool {
+        self.tools.as_ref().is_some_and(|t| !t.is_empty())
+            && !matches!(self.tool_choice, Some(ToolChoice::None))
+    }
+
+    /// Checks if the request enables the thinking mode
+    pub fn thinking_enabled(&self) -> bool {
+        self.thinking.as_ref().is_some_and(Thinking::is_enabled)
+    }
+
+    /// Generates a fingerprint identifying the conversation of this request
+    ///
+    /// Uses `metadata.user_id` if the client provides one, otherwise hashes
+    /// the system prompt and up to `affinity_messages` messages of the first user turn.
+    /// Leading assistant messages, e.g. a greeting, are skipped. Later turns
+    /// are not hashed, so the fingerprint is the same from the first request on.
+    ///
+    /// # Returns
+    /// * `u64` - The fingerprint of the conversation
+    pub fn fingerprint(&self) -> u64 {
+        if let Some(user_id) = self.metadata.as_ref().and_then(|m| m.fields.get("user_id")) {
+            let mut hasher = DefaultHasher::new();
+            user_id.hash(&mut hasher);
+            return hasher.finish();
+        }
+        self.history_fingerprint()
+    }
+
+    /// Generates a fingerprint of the beginning of the conversation of this request
+    ///
+    /// Hashes the system prompt and up to `affinity_messages` messages of the first
+    /// user turn, ignoring `metadata.user_id`, which is shared by all the conversations
+    /// of a user.
+    ///
+    /// # Returns
+    /// * `u64` - The fingerprint of the conversation history
+    pub fn history_fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let n = CLEWDR_CONFIG.load().affinity_messages;
+        self.system.hash(&mut hasher);
+        self.messages
+            .iter()
+            .skip_while(|m| m.role != Role::User)
+            .take_while(|m| m.role == Role::User)
+            .take(n)
+            .for_each(|m| m.hash(&mut hasher));
+        hasher.finish()
+    }
 }
 
 /// Thinking mode in Claude API Request
 #[derive(Deserialize, Serialize, Default, Debug, Clone)]
 pub struct Thinking {
+    #[serde(default, skip_serializing_if = "is_zero")]
     budget_tokens: u64,
     r#type: String,
 }
 
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl Thinking {
+    /// Creates an enabled thinking mode with the given budget
+    pub fn new(budget_tokens: u64) -> Self {
+        Self {
+            budget_tokens,
+            r#type: "enabled".to_string(),
+        }
+    }
+
+    /// Checks if the thinking mode is enabled, i.e. not of type `disabled`
+    pub fn is_enabled(&self) -> bool {
+        self.r#type != "disabled"
+    }
+
+    /// Number of tokens the model may use for thinking, 0 if not set
+    pub fn budget_tokens(&self) -> u64 {
+        self.budget_tokens
+    }
+}
+
 impl From<RequiredMessageParams> for CreateMessageParams {
     fn from(required: RequiredMessageParams) -> Self {
         Self {
@@ -207,14 +294,41 @@ pub enum ContentBlock {
         name: String,
         input: serde_json::Value,
     },
+    /// Thinking content
+    #[serde(rename = "thinking")]
+    Thinking {
+        thinking: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+    },
+    /// Redacted thinking content
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
     /// Tool result content
     #[serde(rename = "tool_result")]
     ToolResult {
         tool_use_id: String,
-        content: String,
+        #[serde(default)]
+        content: ToolResultContent,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        is_error: Option<bool>,
     },
 }
 
+/// Content of a tool result, either plain text or content blocks
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum ToolResultContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+impl Default for ToolResultContent {
+    fn default() -> Self {
+        Self::Text(String::new())
+    }
+}
+
 /// Source of an image
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct ImageSource {
@@ -234,7 +348,7 @@ pub struct ImageUrl {
 }
 
 /// Tool definition
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Tool {
     /// Name of the tool
     pub name: String,
@@ -246,7 +360,7 @@ pub struct Tool {
 }
 
 /// Tool choice configuration
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "type")]
 pub enum ToolChoice {
     /// Let model choose whether to use tools
@@ -258,6 +372,9 @@ pub enum ToolChoice {
     /// Model must use a specific tool
     #[serde(rename = "tool")]
     Tool { name: String },
+    /// Model must not use tools
+    #[serde(rename = "none")]
+    None,
 }
 
 /// Message metadata
@@ -269,7 +386,7 @@ pub struct Metadata {
 }
 
 /// Response from creating a message
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CreateMessageResponse {
     /// Content blocks in the response
     pub content: Vec<ContentBlock>,
@@ -290,8 +407,23 @@ pub struct CreateMessageResponse {
     pub usage: Usage,
 }
 
+impl CreateMessageResponse {
+    /// Estimates the number of output tokens from the content of the message
+    pub fn count_output_tokens(&self) -> u32 {
+        self.content
+            .iter()
+            .map(|block| match block {
+                ContentBlock::Text { text } => count_tokens(text),
+                ContentBlock::Thinking { thinking, .. } => count_tokens(thinking),
+                ContentBlock::ToolUse { input, .. } => count_tokens(&input.to_string()),
+                _ => 0,
+            })
+            .sum::<u64>() as u32
+    }
+}
+
 /// Reason for stopping message generation
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum StopReason {
     EndTurn,
@@ -408,10 +540,14 @@ pub struct MessageStartContent {
     #[serde(rename = "type")]
     pub type_: String,
     pub role: Role,
+    #[serde(default)]
     pub content: Vec<ContentBlock>,
+    #[serde(default)]
     pub model: String,
     pub stop_reason: Option<StopReason>,
     pub stop_sequence: Option<String>,
+    /// Claude.ai does not report usage
+    #[serde(default)]
     pub usage: Usage,
 }
 
@@ -1,5 +1,6 @@
 use colored::{ColoredString, Colorize};
-use std::{fs, path::PathBuf, str::FromStr};
+use std::{fs, path::PathBuf, str::FromStr, sync::LazyLock};
+use tiktoken_rs::{CoreBPE, o200k_base};
 use tracing::error;
 
 use crate::{IS_DEV, config::LOG_DIR, error::ClewdrError};
@@ -13,6 +14,23 @@ pub fn enabled(flag: bool) -> ColoredString {
     }
 }
 
+/// Tokenizer used for approximate token accounting
+static BPE: LazyLock<CoreBPE> = LazyLock::new(|| o200k_base().expect("Failed to load tokenizer"));
+
+/// Counts the approximate number of tokens in a text
+///
+/// # Arguments
+/// * `text` - The text to count tokens for
+///
+/// # Returns
+/// * `u64` - Number of tokens in the text
+pub fn count_tokens(text: &str) -> u64 {
+    if text.is_empty() {
+        return 0;
+    }
+    BPE.encode_with_special_tokens(text).len() as u64
+}
+
 /// Gets and sets up the configuration directory for the application
 ///
 /// In dev, uses the current working directory